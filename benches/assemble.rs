@@ -0,0 +1,19 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+mod synthetic;
+
+use synthetic::synthesize;
+
+fn bench_assemble(c: &mut Criterion) {
+    let options = rm1masm::Options::default();
+    for routines in [64, 320] {
+        let source = synthesize(routines);
+        let words = rm1masm::assemble(&source, &options).unwrap().code.len();
+        c.bench_function(&format!("assemble {words} words"), |b| {
+            b.iter(|| rm1masm::assemble(black_box(&source), &options).unwrap())
+        });
+    }
+}
+
+criterion_group!(benches, bench_assemble);
+criterion_main!(benches);
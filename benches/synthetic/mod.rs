@@ -0,0 +1,22 @@
+/// Builds a microprogram of about `routines * 12` words
+///
+/// Each routine is entered through an IOP jump table and calls a subroutine of its own, so
+/// that placement, symbol resolution and code generation all scale with the program size.
+pub fn synthesize(routines: usize) -> String {
+    let mut source = String::from(".TITLE BENCH\n* FETCH: 000\n\tREAD\n\tPC := PC + 1\n");
+    for table in 0..routines.div_ceil(16) {
+        source += &format!("*\n\tIR := MM\n*\n\tIOP T{table}\n* T{table}:\n");
+        for entry in 0..16.min(routines - table * 16) {
+            source += &format!("*\n\tGOTO R{}\n", table * 16 + entry);
+        }
+    }
+    for routine in 0..routines {
+        source += &format!("* R{routine}:\n\tR0 := R0 + 1\n");
+        for step in 0..8 {
+            source += &format!("* R{routine}S{step}:\n\tCALL R{routine}E\n");
+            source += "\tR1 := R1 - R2\n";
+        }
+        source += &format!("*\n\tGOTO FETCH\n* R{routine}E:\n\tRETURN\n");
+    }
+    source + ".END\n"
+}
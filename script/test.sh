@@ -13,7 +13,7 @@ PROGRAMS="chap4/ex1 chap4/ex2 chap4/ex3 chap4/ex4 chap4/ex5 chap4/ex6 chap4/ex7
 for PROGRAM in ${PROGRAMS}; do
   # Assemble the source program
   SOURCE=$(mktemp)
-  curl -s ${BASE_URL}/${PROGRAM} | iconv -f sjis -t utf8 > ${SOURCE}
+  curl -s ${BASE_URL}/${PROGRAM} > ${SOURCE}
   ACTUAL=$(mktemp)
   ${COMMAND} ${SOURCE} -o ${ACTUAL}
 
@@ -33,7 +33,7 @@ done
 for PROGRAM in "chap5/MICROONE"; do
   # Assemble the source program
   SOURCE=$(mktemp)
-  curl -s ${BASE_URL}/${PROGRAM} | iconv -f sjis -t utf8 | tr -d "\32" > ${SOURCE}
+  curl -s ${BASE_URL}/${PROGRAM} > ${SOURCE}
   ACTUAL=$(mktemp)
   ${COMMAND} ${SOURCE} -o ${ACTUAL}
 
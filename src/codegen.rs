@@ -1,6 +1,10 @@
+use crate::decode::*;
+use crate::error::{Conflict, EncodeError, Error, LbusDestination};
 use crate::parser::*;
 use crate::symbol::SymbolTable;
 
+use logos::Span;
+
 #[derive(Clone, Copy, PartialEq)]
 enum Lb {
     R0l,
@@ -46,23 +50,23 @@ impl From<&Lbus> for Lb {
 
 impl From<&Lb> for MachineCode {
     fn from(from: &Lb) -> Self {
-        (match from {
-            Lb::R0l => 0b0000,
-            Lb::R1l => 0b0001,
-            Lb::R2l => 0b0010,
-            Lb::R3l => 0b0011,
-            Lb::R4l => 0b0100,
-            Lb::R5l => 0b0101,
-            Lb::R6l => 0b0110,
-            Lb::R7l => 0b0111,
-            Lb::Rb => 0b1000,
-            Lb::Rbp => 0b1001,
-            Lb::Pc => 0b1010,
-            Lb::Io => 0b1011,
-            Lb::Mm => 0b1100,
-            Lb::Ir => 0b1101,
-            Lb::Fsr => 0b1110,
-            Lb::Nlb => 0b1111,
+        MachineCode::from(match from {
+            Lb::R0l => LB_R0L,
+            Lb::R1l => LB_R1L,
+            Lb::R2l => LB_R2L,
+            Lb::R3l => LB_R3L,
+            Lb::R4l => LB_R4L,
+            Lb::R5l => LB_R5L,
+            Lb::R6l => LB_R6L,
+            Lb::R7l => LB_R7L,
+            Lb::Rb => LB_RB,
+            Lb::Rbp => LB_RBP,
+            Lb::Pc => LB_PC,
+            Lb::Io => LB_IO,
+            Lb::Mm => LB_MM,
+            Lb::Ir => LB_IR,
+            Lb::Fsr => LB_FSR,
+            Lb::Nlb => LB_NLB,
         }) << (4 + 3 + 3 + 4 + 2 + 4 + 3 + 4 + 9)
     }
 }
@@ -101,12 +105,12 @@ impl From<&Rbus> for Rb {
             Rbus::Ra => Rb::Ra,
             Rbus::Rap => Rb::Rap,
             Rbus::Literal(literal) => {
-                if *literal == 0 {
+                if literal.value == 0 {
                     Rb::Nrb
-                } else if *literal < 512u16 {
-                    Rb::Slt(*literal)
+                } else if literal.value < 512u16 {
+                    Rb::Slt(literal.value)
                 } else {
-                    Rb::Llt(*literal)
+                    Rb::Llt(literal.value)
                 }
             }
         }
@@ -115,20 +119,20 @@ impl From<&Rbus> for Rb {
 
 impl From<&Rb> for MachineCode {
     fn from(from: &Rb) -> Self {
-        (match from {
-            Rb::R0r => 0b0000,
-            Rb::R1r => 0b0001,
-            Rb::R2r => 0b0010,
-            Rb::R3r => 0b0011,
-            Rb::R4r => 0b0100,
-            Rb::R5r => 0b0101,
-            Rb::R6r => 0b0110,
-            Rb::R7r => 0b0111,
-            Rb::Ra => 0b1000,
-            Rb::Rap => 0b1001,
-            Rb::Slt(_) => 0b1010,
-            Rb::Llt(_) => 0b1011,
-            Rb::Nrb => 0b1111,
+        MachineCode::from(match from {
+            Rb::R0r => RB_R0R,
+            Rb::R1r => RB_R1R,
+            Rb::R2r => RB_R2R,
+            Rb::R3r => RB_R3R,
+            Rb::R4r => RB_R4R,
+            Rb::R5r => RB_R5R,
+            Rb::R6r => RB_R6R,
+            Rb::R7r => RB_R7R,
+            Rb::Ra => RB_RA,
+            Rb::Rap => RB_RAP,
+            Rb::Slt(_) => RB_SLT,
+            Rb::Llt(_) => RB_LLT,
+            Rb::Nrb => RB_NRB,
         }) << (3 + 3 + 4 + 2 + 4 + 3 + 4 + 9)
     }
 }
@@ -160,14 +164,14 @@ impl From<&Alu> for Al {
 
 impl From<&Al> for MachineCode {
     fn from(from: &Al) -> Self {
-        (match from {
-            Al::Add => 0b000,
-            Al::Sub => 0b001,
-            Al::And => 0b010,
-            Al::Or => 0b011,
-            Al::Xor => 0b100,
-            Al::Ial => 0b101,
-            Al::Nal => 0b111,
+        MachineCode::from(match from {
+            Al::Add => AL_ADD,
+            Al::Sub => AL_SUB,
+            Al::And => AL_AND,
+            Al::Or => AL_OR,
+            Al::Xor => AL_XOR,
+            Al::Ial => AL_IAL,
+            Al::Nal => AL_NAL,
         }) << (3 + 4 + 2 + 4 + 3 + 4 + 9)
     }
 }
@@ -203,18 +207,19 @@ impl From<&Option<Shift>> for Sh {
 
 impl From<&Sh> for MachineCode {
     fn from(from: &Sh) -> Self {
-        (match from {
-            Sh::Sll => 0b000,
-            Sh::Srl => 0b001,
-            Sh::Sla => 0b010,
-            Sh::Sra => 0b011,
-            Sh::Snx => 0b100,
-            Sh::Swp => 0b101,
-            Sh::Nsh => 0b111,
+        MachineCode::from(match from {
+            Sh::Sll => SH_SLL,
+            Sh::Srl => SH_SRL,
+            Sh::Sla => SH_SLA,
+            Sh::Sra => SH_SRA,
+            Sh::Snx => SH_SNX,
+            Sh::Swp => SH_SWP,
+            Sh::Nsh => SH_NSH,
         }) << (4 + 2 + 4 + 3 + 4 + 9)
     }
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Copy)]
 enum Sb {
     R0s,
@@ -255,29 +260,29 @@ impl From<&Sbus> for Sb {
 
 impl From<&Sb> for MachineCode {
     fn from(from: &Sb) -> Self {
-        (match from {
-            Sb::R0s => 0b0000,
-            Sb::R1s => 0b0001,
-            Sb::R2s => 0b0010,
-            Sb::R3s => 0b0011,
-            Sb::R4s => 0b0100,
-            Sb::R5s => 0b0101,
-            Sb::R6s => 0b0110,
-            Sb::R7s => 0b0111,
-            Sb::Sa => 0b1000,
-            Sb::Sap => 0b1001,
-            Sb::Sb => 0b1010,
-            Sb::Sbp => 0b1011,
-            Sb::Pcs => 0b1100,
-            Sb::Nsb => 0b1111,
+        MachineCode::from(match from {
+            Sb::R0s => SB_R0S,
+            Sb::R1s => SB_R1S,
+            Sb::R2s => SB_R2S,
+            Sb::R3s => SB_R3S,
+            Sb::R4s => SB_R4S,
+            Sb::R5s => SB_R5S,
+            Sb::R6s => SB_R6S,
+            Sb::R7s => SB_R7S,
+            Sb::Sa => SB_SA,
+            Sb::Sap => SB_SAP,
+            Sb::Sb => SB_SB,
+            Sb::Sbp => SB_SBP,
+            Sb::Pcs => SB_PCS,
+            Sb::Nsb => SB_NSB,
         }) << (2 + 4 + 3 + 4 + 9)
     }
 }
 
 struct Calculation(Lb, Rb, Al, Sh, Sb);
 
-impl From<&Option<CalculationStatement>> for Calculation {
-    fn from(from: &Option<CalculationStatement>) -> Self {
+impl From<Option<&CalculationStatement>> for Calculation {
+    fn from(from: Option<&CalculationStatement>) -> Self {
         if let Some(value) = from {
             match value {
                 CalculationStatement::Alu(sbus, statement) => match statement {
@@ -344,8 +349,8 @@ enum Mm {
     Nmm,
 }
 
-impl From<&Option<MemoryStatement>> for Mm {
-    fn from(from: &Option<MemoryStatement>) -> Self {
+impl From<Option<&MemoryStatement>> for Mm {
+    fn from(from: Option<&MemoryStatement>) -> Self {
         if let Some(value) = from {
             match value {
                 MemoryStatement::Read => Mm::Rm,
@@ -359,10 +364,10 @@ impl From<&Option<MemoryStatement>> for Mm {
 
 impl From<&Mm> for MachineCode {
     fn from(from: &Mm) -> Self {
-        (match from {
-            Mm::Rm => 0b00,
-            Mm::Wm => 0b01,
-            Mm::Nmm => 0b11,
+        MachineCode::from(match from {
+            Mm::Rm => MM_RM,
+            Mm::Wm => MM_WM,
+            Mm::Nmm => MM_NMM,
         }) << (4 + 3 + 4 + 9)
     }
 }
@@ -383,17 +388,17 @@ pub enum Sq {
 
 impl From<&Sq> for MachineCode {
     fn from(from: &Sq) -> Self {
-        (match from {
-            Sq::B => 0b0000,
-            Sq::Bp => 0b0001,
-            Sq::Rtn => 0b0010,
-            Sq::Bt => 0b0011,
-            Sq::Bf => 0b0100,
-            Sq::Iop => 0b0101,
-            Sq::Ira => 0b0110,
-            Sq::Iab => 0b0111,
-            Sq::Ei => 0b1000,
-            Sq::Nsq => 0b1111,
+        MachineCode::from(match from {
+            Sq::B => SQ_B,
+            Sq::Bp => SQ_BP,
+            Sq::Rtn => SQ_RTN,
+            Sq::Bt => SQ_BT,
+            Sq::Bf => SQ_BF,
+            Sq::Iop => SQ_IOP,
+            Sq::Ira => SQ_IRA,
+            Sq::Iab => SQ_IAB,
+            Sq::Ei => SQ_EI,
+            Sq::Nsq => SQ_NSQ,
         }) << (3 + 4 + 9)
     }
 }
@@ -411,14 +416,14 @@ enum Ts {
 
 impl From<&Ts> for MachineCode {
     fn from(from: &Ts) -> Self {
-        (match from {
-            Ts::Zer => 0b000,
-            Ts::Neg => 0b001,
-            Ts::Cry => 0b010,
-            Ts::Ov => 0b011,
-            Ts::T => 0b100,
-            Ts::Cz => 0b101,
-            Ts::Nts => 0b111,
+        MachineCode::from(match from {
+            Ts::Zer => TS_ZER,
+            Ts::Neg => TS_NEG,
+            Ts::Cry => TS_CRY,
+            Ts::Ov => TS_OV,
+            Ts::T => TS_T,
+            Ts::Cz => TS_CZ,
+            Ts::Nts => TS_NTS,
         }) << (4 + 9)
     }
 }
@@ -443,8 +448,8 @@ enum Ex {
     Nex,
 }
 
-impl From<&Option<ExStatement>> for Ex {
-    fn from(from: &Option<ExStatement>) -> Self {
+impl From<Option<&ExStatement>> for Ex {
+    fn from(from: Option<&ExStatement>) -> Self {
         if let Some(value) = from {
             match value {
                 ExStatement::DecrimentC => Ex::Cm1,
@@ -481,31 +486,31 @@ impl From<&Option<ExStatement>> for Ex {
 
 impl From<&Ex> for MachineCode {
     fn from(from: &Ex) -> Self {
-        (match from {
-            Ex::Cm1 => 0b0000,
-            Ex::Fls => 0b0001,
-            Ex::Asc => 0b0010,
-            Ex::As1 => 0b0011,
-            Ex::Lir(_) => 0b0100,
-            Ex::Lio(_) => 0b0101,
-            Ex::Sc(_) => 0b0110,
-            Ex::Eio => 0b0111,
-            Ex::St => 0b1000,
-            Ex::Rt => 0b1001,
-            Ex::Ina => 0b1010,
-            Ex::Inb => 0b1011,
-            Ex::Dcb => 0b1100,
-            Ex::Hlt => 0b1101,
-            Ex::Ov => 0b1110,
-            Ex::Nex => 0b1111,
+        MachineCode::from(match from {
+            Ex::Cm1 => EX_CM1,
+            Ex::Fls => EX_FLS,
+            Ex::Asc => EX_ASC,
+            Ex::As1 => EX_AS1,
+            Ex::Lir(_) => EX_LIR,
+            Ex::Lio(_) => EX_LIO,
+            Ex::Sc(_) => EX_SC,
+            Ex::Eio => EX_EIO,
+            Ex::St => EX_ST,
+            Ex::Rt => EX_RT,
+            Ex::Ina => EX_INA,
+            Ex::Inb => EX_INB,
+            Ex::Dcb => EX_DCB,
+            Ex::Hlt => EX_HLT,
+            Ex::Ov => EX_OV,
+            Ex::Nex => EX_NEX,
         }) << 9
     }
 }
 
 struct TestAndSequence<'a>(Ts, Sq, Option<&'a str>);
 
-impl<'a> From<&Option<TestAndSequenceStatement<'a>>> for TestAndSequence<'a> {
-    fn from(from: &Option<TestAndSequenceStatement<'a>>) -> Self {
+impl<'a> From<Option<&TestAndSequenceStatement<'a>>> for TestAndSequence<'a> {
+    fn from(from: Option<&TestAndSequenceStatement<'a>>) -> Self {
         if let Some(value) = from {
             match value {
                 TestAndSequenceStatement::Goto(label) => {
@@ -566,11 +571,11 @@ pub struct MicroInstruction<'a> {
 }
 
 impl<'a> MicroInstruction<'a> {
-    pub fn resolve(&'a self, table: &SymbolTable) -> Result<Self, ()> {
-        match self.lsb.resolve(table) {
-            Ok(lsb) => Ok(Self { lsb, ..*self }),
-            Err(_) => Err(()),
-        }
+    pub fn resolve(&'a self, table: &SymbolTable) -> Result<Self, EncodeError> {
+        Ok(Self {
+            lsb: self.lsb.resolve(table)?,
+            ..*self
+        })
     }
 }
 
@@ -583,15 +588,11 @@ enum MicroInstructionLSB16<'a> {
 }
 
 impl<'a> MicroInstructionLSB16<'a> {
-    pub fn resolve(&'a self, table: &SymbolTable) -> Result<Self, ()> {
+    pub fn resolve(&'a self, table: &SymbolTable) -> Result<Self, EncodeError> {
         match self {
-            MicroInstructionLSB16::WithReference(ts, ex, reference) => {
-                if let Ok(reference) = reference.resolve(table) {
-                    Ok(MicroInstructionLSB16::WithReference(*ts, *ex, reference))
-                } else {
-                    Err(())
-                }
-            }
+            MicroInstructionLSB16::WithReference(ts, ex, reference) => Ok(
+                MicroInstructionLSB16::WithReference(*ts, *ex, reference.resolve(table)?),
+            ),
             _ => Ok(*self),
         }
     }
@@ -604,13 +605,13 @@ enum Reference<'a> {
 }
 
 impl<'a> Reference<'a> {
-    pub fn resolve(&'a self, table: &SymbolTable) -> Result<Self, ()> {
+    pub fn resolve(&'a self, table: &SymbolTable) -> Result<Self, EncodeError> {
         match self {
             Reference::Unresolved(label) => {
                 if let Some(address) = table.get(label) {
                     Ok(Reference::Resolved(*address))
                 } else {
-                    Err(())
+                    Err(EncodeError::UnresolvedReference(label.to_string()))
                 }
             }
             Reference::Resolved(_) => Ok(*self),
@@ -618,15 +619,40 @@ impl<'a> Reference<'a> {
     }
 }
 
+/// Tells which field keeps a long literal out of the 16 LSBs
+fn long_literal_conflict(ts: Ts, reference: Option<&str>) -> EncodeError {
+    EncodeError::LongLiteral(if reference.is_some() {
+        Conflict::Reference
+    } else if !matches!(ts, Ts::Nts) {
+        Conflict::Test
+    } else {
+        Conflict::Ex
+    })
+}
+
 impl<'a> TryFrom<&'a Instruction<'a>> for MicroInstruction<'a> {
-    type Error = ();
+    type Error = EncodeError;
 
     fn try_from(value: &Instruction<'a>) -> Result<Self, Self::Error> {
-        let TestAndSequence(ts, sq, reference) =
-            TestAndSequence::from(&value.test_and_sequence_statement);
-        let mm = Mm::from(&value.memory_statement);
-        let Calculation(lb, rb, al, sh, sb) = Calculation::from(&value.calculation_statement);
-        let ex = Ex::from(&value.ex_statement);
+        let TestAndSequence(ts, sq, reference) = TestAndSequence::from(
+            value
+                .test_and_sequence_statement
+                .as_ref()
+                .map(|(statement, _)| statement),
+        );
+        let mm = Mm::from(
+            value
+                .memory_statement
+                .as_ref()
+                .map(|(statement, _)| statement),
+        );
+        let Calculation(lb, rb, al, sh, sb) = Calculation::from(
+            value
+                .calculation_statement
+                .as_ref()
+                .map(|(statement, _)| statement),
+        );
+        let ex = Ex::from(value.ex_statement.as_ref().map(|(statement, _)| statement));
 
         if let Rb::Llt(literal) = rb {
             if matches!(ts, Ts::Nts) && reference.is_none() && matches!(ex, Ex::Nex) {
@@ -641,7 +667,7 @@ impl<'a> TryFrom<&'a Instruction<'a>> for MicroInstruction<'a> {
                     lsb: MicroInstructionLSB16::Llt(literal),
                 })
             } else {
-                Err(())
+                Err(long_literal_conflict(ts, reference))
             }
         } else if let Rb::Slt(literal) = rb {
             if reference.is_none() {
@@ -656,7 +682,7 @@ impl<'a> TryFrom<&'a Instruction<'a>> for MicroInstruction<'a> {
                     lsb: MicroInstructionLSB16::TsExAndLt(ts, ex, literal),
                 })
             } else {
-                Err(())
+                Err(EncodeError::ShortLiteral)
             }
         } else if let Ex::Lir(lbus) = ex {
             if let Some(src) = lbus {
@@ -680,7 +706,7 @@ impl<'a> TryFrom<&'a Instruction<'a>> for MicroInstruction<'a> {
                         },
                     })
                 } else {
-                    Err(())
+                    Err(EncodeError::LbusSource(LbusDestination::Ir))
                 }
             } else {
                 Ok(Self {
@@ -724,7 +750,7 @@ impl<'a> TryFrom<&'a Instruction<'a>> for MicroInstruction<'a> {
                         },
                     })
                 } else {
-                    Err(())
+                    Err(EncodeError::LbusSource(LbusDestination::Io))
                 }
             } else {
                 Ok(Self {
@@ -762,7 +788,7 @@ impl<'a> TryFrom<&'a Instruction<'a>> for MicroInstruction<'a> {
                                 lsb: MicroInstructionLSB16::Llt(literal),
                             })
                         } else {
-                            Err(())
+                            Err(long_literal_conflict(ts, reference))
                         }
                     }
                     Rb::Slt(literal) => {
@@ -778,7 +804,7 @@ impl<'a> TryFrom<&'a Instruction<'a>> for MicroInstruction<'a> {
                                 lsb: MicroInstructionLSB16::TsExAndLt(ts, ex, literal),
                             })
                         } else {
-                            Err(())
+                            Err(EncodeError::ShortLiteral)
                         }
                     }
                     _ => Ok(Self {
@@ -801,7 +827,7 @@ impl<'a> TryFrom<&'a Instruction<'a>> for MicroInstruction<'a> {
                     }),
                 }
             } else {
-                Err(())
+                Err(EncodeError::RbusSource)
             }
         } else {
             Ok(Self {
@@ -823,7 +849,7 @@ impl<'a> TryFrom<&'a Instruction<'a>> for MicroInstruction<'a> {
 }
 
 impl<'a> TryFrom<&MicroInstruction<'a>> for MachineCode {
-    type Error = ();
+    type Error = EncodeError;
 
     fn try_from(value: &MicroInstruction) -> Result<Self, Self::Error> {
         match value.lsb {
@@ -855,7 +881,9 @@ impl<'a> TryFrom<&MicroInstruction<'a>> for MachineCode {
                 | MachineCode::from(&ex)
                 | slt as MachineCode),
             MicroInstructionLSB16::WithReference(ts, ex, reference) => match reference {
-                Reference::Unresolved(_) => Err(()),
+                Reference::Unresolved(label) => {
+                    Err(EncodeError::UnresolvedReference(label.to_string()))
+                }
                 Reference::Resolved(literal) => Ok(MachineCode::from(&value.lb)
                     | MachineCode::from(&value.rb)
                     | MachineCode::from(&value.al)
@@ -886,27 +914,79 @@ impl<'a> TryFrom<&MicroInstruction<'a>> for MachineCode {
     }
 }
 
-pub fn generate<'a>(
-    instructions: &'a Vec<Instruction<'a>>,
-) -> Result<Vec<(MachineAddress, MicroInstruction<'a>)>, ()> {
-    let mut ret = Vec::<(MachineAddress, MicroInstruction)>::new();
+/// Finds the statement that `reason` is about
+pub(crate) fn statement_span(instruction: &Instruction, reason: &EncodeError) -> Span {
+    let span = |statement: Option<Span>| statement.unwrap_or_else(|| instruction.span.clone());
+    match reason {
+        EncodeError::LongLiteral(_) | EncodeError::ShortLiteral => span(
+            instruction
+                .calculation_statement
+                .as_ref()
+                .map(|(_, span)| span.clone())
+                .or_else(|| {
+                    instruction
+                        .ex_statement
+                        .as_ref()
+                        .map(|(_, span)| span.clone())
+                }),
+        ),
+        EncodeError::LbusSource(_) | EncodeError::RbusSource => span(
+            instruction
+                .ex_statement
+                .as_ref()
+                .map(|(_, span)| span.clone()),
+        ),
+        EncodeError::UnresolvedReference(_) => span(
+            instruction
+                .test_and_sequence_statement
+                .as_ref()
+                .map(|(_, span)| span.clone()),
+        ),
+    }
+}
+
+/// Encodes one instruction, with its references resolved by `symbol_table`
+pub fn encode(
+    instruction: &Instruction,
+    symbol_table: &SymbolTable,
+) -> Result<MachineCode, EncodeError> {
+    MicroInstruction::try_from(instruction)
+        .and_then(|code| {
+            code.resolve(symbol_table)
+                .map(|code| MachineCode::try_from(&code))
+        })
+        .and_then(|code| code)
+}
 
-    for instruction in instructions {
-        let address = if let Some(constant) = instruction.address {
-            constant
-        } else {
-            return Err(());
-        };
+pub fn generate(
+    instructions: &[Instruction],
+    symbol_table: &SymbolTable,
+) -> Result<Vec<(MachineAddress, MachineCode)>, Vec<Error>> {
+    let mut ret = Vec::<(MachineAddress, MachineCode)>::new();
+    let mut errors = Vec::<Error>::new();
 
-        let code = match MicroInstruction::try_from(instruction) {
-            Ok(code) => code,
-            Err(_) => {
-                return Err(());
-            }
+    for instruction in instructions {
+        // instructions left without an address are reported by placement::place
+        let address = match instruction.address {
+            Some(address) => address,
+            None => continue,
         };
 
-        ret.push((address, code));
+        match encode(instruction, symbol_table) {
+            Ok(code) => ret.push((address, code)),
+            // unresolved symbols are reported by symbol::check_unresolved_symbols
+            Err(EncodeError::UnresolvedReference(_)) => (),
+            Err(reason) => errors.push(Error::Encoding {
+                location: instruction.location(),
+                span: statement_span(instruction, &reason),
+                reason,
+            }),
+        }
     }
 
-    Ok(ret)
+    if errors.is_empty() {
+        Ok(ret)
+    } else {
+        Err(errors)
+    }
 }
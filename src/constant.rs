@@ -0,0 +1,262 @@
+use crate::error::Error;
+use crate::expression::{self, Expression};
+use crate::lexer;
+use crate::parser::{self, Spanned};
+use crate::token::Token;
+
+use logos::Span;
+
+use std::collections::{HashMap, HashSet};
+
+/// Named value defined by `.EQU NAME VALUE`, `.DEFINE NAME VALUE` or `-D NAME=VALUE`
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Constant {
+    value: u16,
+    /// Span of the name in the definition, or `None` for a constant defined by [`Options`]
+    ///
+    /// [`Options`]: crate::Options
+    span: Option<Span>,
+}
+
+/// Constants of a program, which the parser reads wherever a number is expected
+#[derive(Debug, Default)]
+pub struct Constants {
+    constants: HashMap<String, Constant>,
+    /// Names read by the values of other constants
+    used: HashSet<String>,
+}
+
+impl Constants {
+    /// Value of the constant `name`
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.constants.get(name).map(|constant| constant.value)
+    }
+
+    /// Reports every constant that is neither in `used` nor read by another constant, those
+    /// defined on the command line first
+    pub fn check_unused(&self, used: &HashSet<String>) -> Vec<Error> {
+        let mut unused = self
+            .constants
+            .iter()
+            .filter(|(name, _)| !used.contains(*name) && !self.used.contains(*name))
+            .collect::<Vec<_>>();
+        unused
+            .sort_by_key(|(name, constant)| (constant.span.as_ref().map(|span| span.start), *name));
+        unused
+            .into_iter()
+            .map(|(name, constant)| Error::UnusedConstant {
+                name: name.clone(),
+                span: constant.span.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Calculates `text` as an expression written as in a source program, such as `0FF`, `D"255`
+/// or `1<<4`, without constants and labels
+pub fn parse_value(text: &str) -> Option<u16> {
+    let tokens = lexer::tokenize(text);
+    if tokens.iter().any(|(token, _)| *token == Token::Error) {
+        return None;
+    }
+    let expression = parser::parse_expression(tokens, &Constants::default()).ok()?;
+    expression::evaluate_constant(&expression).ok()
+}
+
+/// Takes the lines defining constants out of `tokens`, leaving their line endings
+///
+/// The value of a definition is an expression of numbers and constants defined before it.
+/// `defines` are defined before the program, so that a program redefining one of them is
+/// reported.
+pub fn collect<'a>(
+    tokens: Vec<(Token<'a>, Span)>,
+    defines: &[(String, u16)],
+) -> (Vec<(Token<'a>, Span)>, Constants, Vec<Error>) {
+    let mut constants = Constants {
+        constants: defines
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.clone(),
+                    Constant {
+                        value: *value,
+                        span: None,
+                    },
+                )
+            })
+            .collect(),
+        used: HashSet::new(),
+    };
+    let mut errors = Vec::<Error>::new();
+
+    let mut kept = Vec::with_capacity(tokens.len());
+    let mut lines = tokens.into_iter();
+    while let Some(first) = lines.next() {
+        let mut line = vec![first];
+        while line.last().unwrap().0 != Token::Eol {
+            match lines.next() {
+                Some(token) => line.push(token),
+                None => break,
+            }
+        }
+        let eol = match line.last() {
+            Some((Token::Eol, _)) => line.pop(),
+            _ => None,
+        };
+
+        match line.as_slice() {
+            [(Token::DotString("EQU" | "DEFINE"), _), definition @ ..] => {
+                match definition {
+                    [(Token::String(name), span), value @ ..] if !value.is_empty() => {
+                        match parser::parse_expression(value.to_vec(), &constants) {
+                            Ok(value) => {
+                                expression::constant_names(&value, &mut constants.used);
+                                define(&mut constants, &mut errors, name, span, &value);
+                            }
+                            Err(errs) => errors.extend(
+                                errs.into_iter()
+                                    .filter(|err| err.found() != Some(&Token::Error))
+                                    .map(|err| Error::UnexpectedToken { span: err.span() }),
+                            ),
+                        }
+                    }
+                    [(Token::String(_), _)] | [] => errors.push(Error::UnexpectedToken {
+                        span: eol
+                            .as_ref()
+                            .map_or(span_end(&line), |(_, span)| span.clone()),
+                    }),
+                    [(_, span), ..] => errors.push(Error::UnexpectedToken { span: span.clone() }),
+                }
+                kept.extend(eol);
+            }
+            _ => {
+                kept.extend(line);
+                kept.extend(eol);
+            }
+        }
+    }
+
+    (kept, constants, errors)
+}
+
+/// Defines the constant `name`, written at `span`, as the value of `value`
+fn define(
+    constants: &mut Constants,
+    errors: &mut Vec<Error>,
+    name: &str,
+    span: &Span,
+    value: &Spanned<Expression>,
+) {
+    match (
+        expression::evaluate_constant(value),
+        constants.constants.get(name),
+    ) {
+        (Err(error), _) => errors.push(error),
+        (Ok(_), Some(previous)) => errors.push(Error::DuplicateConstant {
+            name: name.to_string(),
+            span: span.clone(),
+            previous: previous.span.clone(),
+        }),
+        (Ok(value), None) => {
+            constants.constants.insert(
+                name.to_string(),
+                Constant {
+                    value,
+                    span: Some(span.clone()),
+                },
+            );
+        }
+    }
+}
+
+/// Empty span at the end of `line`
+fn span_end(line: &[(Token, Span)]) -> Span {
+    let end = line.last().map_or(0, |(_, span)| span.end);
+    end..end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect, parse_value};
+    use crate::error::{Error, ExpressionError};
+    use crate::lexer::tokenize;
+    use crate::token::Token;
+
+    #[test]
+    fn test_collect() {
+        let source = ".EQU MASK 0FF\n.DEFINE PORT MASK\n\tR0 := R0 AND MASK\n.EQU UNUSED D\"10\n\
+                      .EQU WIDE (MASK << 4) | 0F\n";
+        let (tokens, constants, errors) = collect(tokenize(source), &[("N".to_string(), 3)]);
+        assert_eq!(errors, []);
+        assert_eq!(tokens[0], (Token::Eol, 13..14));
+        assert_eq!(tokens[2], (Token::String("R0"), 33..35));
+        assert_eq!(constants.get("PORT"), Some(0x0FF));
+        assert_eq!(constants.get("MASK"), Some(0x0FF));
+        assert_eq!(constants.get("N"), Some(3));
+        assert_eq!(constants.get("WIDE"), Some(0xFFF));
+        // MASK is read by the values of PORT and WIDE
+        let used = ["PORT", "WIDE"].map(String::from).into();
+        assert_eq!(
+            constants.check_unused(&used),
+            [
+                Error::UnusedConstant {
+                    name: "N".to_string(),
+                    span: None
+                },
+                Error::UnusedConstant {
+                    name: "UNUSED".to_string(),
+                    span: Some(56..62)
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collect_errors() {
+        let source =
+            ".EQU N 1\n.EQU MASK 0FF\n.EQU MASK 0F\n.EQU X\n.EQU Y Z\n.EQU W 1+\n.EQU V 1-2\n";
+        let (_, _, errors) = collect(tokenize(source), &[("N".to_string(), 3)]);
+        assert_eq!(
+            errors,
+            [
+                Error::DuplicateConstant {
+                    name: "N".to_string(),
+                    span: 5..6,
+                    previous: None
+                },
+                Error::DuplicateConstant {
+                    name: "MASK".to_string(),
+                    span: 28..32,
+                    previous: Some(14..18)
+                },
+                Error::UnexpectedToken { span: 42..43 },
+                Error::Expression {
+                    span: 50..51,
+                    reason: ExpressionError::LabelInConstant("Z".to_string())
+                },
+                Error::UnexpectedToken { span: 61..61 },
+                Error::Expression {
+                    span: 69..72,
+                    reason: ExpressionError::OutOfRange {
+                        value: -1,
+                        max: 0xFFFF
+                    }
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value("0FF"), Some(0xFF));
+        assert_eq!(parse_value("FF"), Some(0xFF));
+        assert_eq!(parse_value("D\"255"), Some(255));
+        assert_eq!(parse_value("B\"101"), Some(5));
+        assert_eq!(parse_value("1<<4"), Some(0x10));
+        assert_eq!(parse_value("HIGH(1234) + 1"), Some(0x13));
+        assert_eq!(parse_value("MASK"), None);
+        assert_eq!(parse_value("1 2"), None);
+        assert_eq!(parse_value("0-1"), None);
+        assert_eq!(parse_value("1 ? 2"), None);
+    }
+}
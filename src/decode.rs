@@ -0,0 +1,280 @@
+use crate::parser::{MachineAddress, MachineCode};
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use std::fmt;
+
+const LB: [&str; 16] = [
+    "R0L", "R1L", "R2L", "R3L", "R4L", "R5L", "R6L", "R7L", "RB", "RBP", "PC", "IO", "MM", "IR",
+    "FSR", "NLB",
+];
+const RB: [&str; 16] = [
+    "R0R", "R1R", "R2R", "R3R", "R4R", "R5R", "R6R", "R7R", "RA", "RAP", "SLT", "LLT", "?", "?",
+    "?", "NRB",
+];
+const AL: [&str; 8] = ["ADD", "SUB", "AND", "OR", "XOR", "IAL", "?", "NAL"];
+const SH: [&str; 8] = ["SLL", "SRL", "SLA", "SRA", "SNX", "SWP", "?", "NSH"];
+const SB: [&str; 16] = [
+    "R0S", "R1S", "R2S", "R3S", "R4S", "R5S", "R6S", "R7S", "SA", "SAP", "SB", "SBP", "PCS", "?",
+    "?", "NSB",
+];
+const MM: [&str; 4] = ["RM", "WM", "?", "NMM"];
+const SQ: [&str; 16] = [
+    "B", "BP", "RTN", "BT", "BF", "IOP", "IRA", "IAB", "EI", "?", "?", "?", "?", "?", "?", "NSQ",
+];
+const TS: [&str; 8] = ["ZER", "NEG", "CRY", "OV", "T", "CZ", "?", "NTS"];
+const EX: [&str; 16] = [
+    "CM1", "FLS", "ASC", "AS1", "LIR", "LIO", "SC", "EIO", "ST", "RT", "INA", "INB", "DCB", "HLT",
+    "OV", "NEX",
+];
+
+/// Codes of the fields, which `codegen` encodes with and the disassembler decodes
+pub(crate) const LB_R0L: u8 = 0b0000;
+pub(crate) const LB_R1L: u8 = 0b0001;
+pub(crate) const LB_R2L: u8 = 0b0010;
+pub(crate) const LB_R3L: u8 = 0b0011;
+pub(crate) const LB_R4L: u8 = 0b0100;
+pub(crate) const LB_R5L: u8 = 0b0101;
+pub(crate) const LB_R6L: u8 = 0b0110;
+pub(crate) const LB_R7L: u8 = 0b0111;
+pub(crate) const LB_RB: u8 = 0b1000;
+pub(crate) const LB_RBP: u8 = 0b1001;
+pub(crate) const LB_PC: u8 = 0b1010;
+pub(crate) const LB_IO: u8 = 0b1011;
+pub(crate) const LB_MM: u8 = 0b1100;
+pub(crate) const LB_IR: u8 = 0b1101;
+pub(crate) const LB_FSR: u8 = 0b1110;
+pub(crate) const LB_NLB: u8 = 0b1111;
+pub(crate) const RB_R0R: u8 = 0b0000;
+pub(crate) const RB_R1R: u8 = 0b0001;
+pub(crate) const RB_R2R: u8 = 0b0010;
+pub(crate) const RB_R3R: u8 = 0b0011;
+pub(crate) const RB_R4R: u8 = 0b0100;
+pub(crate) const RB_R5R: u8 = 0b0101;
+pub(crate) const RB_R6R: u8 = 0b0110;
+pub(crate) const RB_R7R: u8 = 0b0111;
+pub(crate) const RB_RA: u8 = 0b1000;
+pub(crate) const RB_RAP: u8 = 0b1001;
+pub(crate) const RB_SLT: u8 = 0b1010;
+pub(crate) const RB_LLT: u8 = 0b1011;
+pub(crate) const RB_NRB: u8 = 0b1111;
+pub(crate) const AL_ADD: u8 = 0b000;
+pub(crate) const AL_SUB: u8 = 0b001;
+pub(crate) const AL_AND: u8 = 0b010;
+pub(crate) const AL_OR: u8 = 0b011;
+pub(crate) const AL_XOR: u8 = 0b100;
+pub(crate) const AL_IAL: u8 = 0b101;
+pub(crate) const AL_NAL: u8 = 0b111;
+pub(crate) const SH_SLL: u8 = 0b000;
+pub(crate) const SH_SRL: u8 = 0b001;
+pub(crate) const SH_SLA: u8 = 0b010;
+pub(crate) const SH_SRA: u8 = 0b011;
+pub(crate) const SH_SNX: u8 = 0b100;
+pub(crate) const SH_SWP: u8 = 0b101;
+pub(crate) const SH_NSH: u8 = 0b111;
+pub(crate) const SB_R0S: u8 = 0b0000;
+pub(crate) const SB_R1S: u8 = 0b0001;
+pub(crate) const SB_R2S: u8 = 0b0010;
+pub(crate) const SB_R3S: u8 = 0b0011;
+pub(crate) const SB_R4S: u8 = 0b0100;
+pub(crate) const SB_R5S: u8 = 0b0101;
+pub(crate) const SB_R6S: u8 = 0b0110;
+pub(crate) const SB_R7S: u8 = 0b0111;
+pub(crate) const SB_SA: u8 = 0b1000;
+pub(crate) const SB_SAP: u8 = 0b1001;
+pub(crate) const SB_SB: u8 = 0b1010;
+pub(crate) const SB_SBP: u8 = 0b1011;
+pub(crate) const SB_PCS: u8 = 0b1100;
+pub(crate) const SB_NSB: u8 = 0b1111;
+pub(crate) const MM_RM: u8 = 0b00;
+pub(crate) const MM_WM: u8 = 0b01;
+pub(crate) const MM_NMM: u8 = 0b11;
+pub(crate) const SQ_B: u8 = 0b0000;
+pub(crate) const SQ_BP: u8 = 0b0001;
+pub(crate) const SQ_RTN: u8 = 0b0010;
+pub(crate) const SQ_BT: u8 = 0b0011;
+pub(crate) const SQ_BF: u8 = 0b0100;
+pub(crate) const SQ_IOP: u8 = 0b0101;
+pub(crate) const SQ_IRA: u8 = 0b0110;
+pub(crate) const SQ_IAB: u8 = 0b0111;
+pub(crate) const SQ_EI: u8 = 0b1000;
+pub(crate) const SQ_NSQ: u8 = 0b1111;
+pub(crate) const TS_ZER: u8 = 0b000;
+pub(crate) const TS_NEG: u8 = 0b001;
+pub(crate) const TS_CRY: u8 = 0b010;
+pub(crate) const TS_OV: u8 = 0b011;
+pub(crate) const TS_T: u8 = 0b100;
+pub(crate) const TS_CZ: u8 = 0b101;
+pub(crate) const TS_NTS: u8 = 0b111;
+pub(crate) const EX_CM1: u8 = 0b0000;
+pub(crate) const EX_FLS: u8 = 0b0001;
+pub(crate) const EX_ASC: u8 = 0b0010;
+pub(crate) const EX_AS1: u8 = 0b0011;
+pub(crate) const EX_LIR: u8 = 0b0100;
+pub(crate) const EX_LIO: u8 = 0b0101;
+pub(crate) const EX_SC: u8 = 0b0110;
+pub(crate) const EX_EIO: u8 = 0b0111;
+pub(crate) const EX_ST: u8 = 0b1000;
+pub(crate) const EX_RT: u8 = 0b1001;
+pub(crate) const EX_INA: u8 = 0b1010;
+pub(crate) const EX_INB: u8 = 0b1011;
+pub(crate) const EX_DCB: u8 = 0b1100;
+pub(crate) const EX_HLT: u8 = 0b1101;
+pub(crate) const EX_OV: u8 = 0b1110;
+pub(crate) const EX_NEX: u8 = 0b1111;
+
+/// Fields of a 40-bit microinstruction word
+///
+/// Fields that share the 16 LSBs with a literal or a branch address are `None` when the word
+/// uses those bits for the literal or the address.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Fields {
+    pub lb: u8,
+    pub rb: u8,
+    pub al: u8,
+    pub sh: u8,
+    pub sb: u8,
+    pub mm: u8,
+    pub sq: u8,
+    pub ts: Option<u8>,
+    pub ex: Option<u8>,
+    /// Short literal in the 9 LSBs, or long literal in the 16 LSBs
+    pub literal: Option<u16>,
+    /// Branch target, or the base address of a dispatch
+    pub target: Option<MachineAddress>,
+}
+
+impl Fields {
+    /// Splits the word placed at `address` into its fields
+    ///
+    /// BT, BF and EI only encode the 9 LSBs of their target, which is taken to be in the
+    /// 512-word page of `address`.
+    pub fn decode(address: MachineAddress, code: MachineCode) -> Self {
+        let field = |shift: u32, width: u32| ((code >> shift) & ((1 << width) - 1)) as u8;
+        let ts = field(13, 3);
+        let low = (code & 0x1FF) as MachineAddress;
+
+        let mut fields = Fields {
+            lb: field(36, 4),
+            rb: field(32, 4),
+            al: field(29, 3),
+            sh: field(26, 3),
+            sb: field(22, 4),
+            mm: field(20, 2),
+            sq: field(16, 4),
+            ts: Some(ts),
+            ex: Some(field(9, 4)),
+            literal: None,
+            target: None,
+        };
+
+        // `C :=` with a literal source is encoded like an Rbus literal
+        if fields.rb == RB_LLT {
+            fields.ts = None;
+            fields.ex = None;
+            fields.literal = Some((code & 0xFFFF) as u16);
+            return fields;
+        }
+        if fields.rb == RB_SLT {
+            fields.literal = Some(low);
+            return fields;
+        }
+
+        let ts = MachineAddress::from(ts);
+        fields.target = match fields.sq {
+            SQ_B | SQ_BP => {
+                fields.ts = None;
+                Some((ts << 9) | low)
+            }
+            SQ_BT | SQ_BF => Some((address & 0xE00) | low),
+            SQ_EI if fields.ts != Some(TS_NTS) => Some((address & 0xE00) | low),
+            SQ_IOP => Some(low << 4),
+            SQ_IRA => {
+                fields.ts = None;
+                Some(((ts & 0x1) << 11) | ((low & 0x1FC) << 2) | (low & 0x003))
+            }
+            SQ_IAB => {
+                fields.ts = None;
+                Some(((ts & 0x1) << 11) | ((low & 0x1F0) << 2) | (low & 0x003))
+            }
+            _ => None,
+        };
+        fields
+    }
+
+    /// Mnemonics of the LB, RB, AL, SH, SB, MM, SQ, TS and EX fields
+    pub fn mnemonics(&self) -> [&'static str; 9] {
+        [
+            LB[usize::from(self.lb)],
+            RB[usize::from(self.rb)],
+            AL[usize::from(self.al)],
+            SH[usize::from(self.sh)],
+            SB[usize::from(self.sb)],
+            MM[usize::from(self.mm)],
+            SQ[usize::from(self.sq)],
+            self.ts.map_or("", |ts| TS[usize::from(ts)]),
+            self.ex.map_or("", |ex| EX[usize::from(ex)]),
+        ]
+    }
+}
+
+impl fmt::Display for Fields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [lb, rb, al, sh, sb, mm, sq, ts, ex] = self.mnemonics();
+        write!(
+            f,
+            "{lb:<4} {rb:<4} {al:<4} {sh:<4} {sb:<4} {mm:<4} {sq:<4} {ts:<4} {ex:<4}"
+        )?;
+        match (self.literal, self.target) {
+            (Some(literal), _) => write!(f, " {literal:04X}"),
+            (None, Some(target)) => write!(f, " {target:04X}"),
+            (None, None) => write!(f, "     "),
+        }
+    }
+}
+
+/// Serializes the fields by their mnemonics, leaving out `ts` and `ex` when the word has no such
+/// field
+impl Serialize for Fields {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let [lb, rb, al, sh, sb, mm, sq, ts, ex] = self.mnemonics();
+        let mut state = serializer.serialize_map(None)?;
+        state.serialize_entry("lb", lb)?;
+        state.serialize_entry("rb", rb)?;
+        state.serialize_entry("al", al)?;
+        state.serialize_entry("sh", sh)?;
+        state.serialize_entry("sb", sb)?;
+        state.serialize_entry("mm", mm)?;
+        state.serialize_entry("sq", sq)?;
+        if self.ts.is_some() {
+            state.serialize_entry("ts", ts)?;
+        }
+        if self.ex.is_some() {
+            state.serialize_entry("ex", ex)?;
+        }
+        state.serialize_entry("literal", &self.literal)?;
+        state.serialize_entry("target", &self.target)?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fields;
+
+    #[test]
+    fn test_decode() {
+        let fields = Fields::decode(0x000, 0xAA1F0FFE01);
+        assert_eq!(
+            fields.mnemonics(),
+            ["PC", "SLT", "ADD", "NSH", "PCS", "RM", "NSQ", "NTS", "NEX"]
+        );
+        assert_eq!(fields.literal, Some(0x001));
+
+        assert_eq!(Fields::decode(0x014, 0xFB7CFF1234).literal, Some(0x1234));
+        assert_eq!(Fields::decode(0x002, 0xFFFFF5FE01).target, Some(0x010));
+        assert_eq!(Fields::decode(0x015, 0xFFFFF8FE00).target, None);
+        assert_eq!(Fields::decode(0x013, 0xFFFFF11E16).target, Some(0x016));
+        assert_eq!(Fields::decode(0x012, 0x233CB30013).target, Some(0x013));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::decode::Fields;
+use crate::parser::{MachineAddress, MachineCode};
+use crate::symbol;
+use crate::Object;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Field that differs between two words
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FieldChange {
+    /// Name of the field, such as `SQ` or `branch target`
+    pub field: &'static str,
+    pub first: String,
+    pub second: String,
+}
+
+/// Words that two objects place at one address, when they differ
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WordDifference {
+    pub address: MachineAddress,
+    /// Word of the first object, if it has one at the address
+    pub first: Option<MachineCode>,
+    /// Word of the second object, if it has one at the address
+    pub second: Option<MachineCode>,
+    /// Label placed at the address by either object
+    pub label: Option<String>,
+    /// Fields that differ, when both objects have a word
+    pub changes: Vec<FieldChange>,
+}
+
+impl fmt::Display for WordDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:03X}", self.address)?;
+        if let Some(label) = &self.label {
+            write!(f, " {label}")?;
+        }
+        match (self.first, self.second) {
+            (Some(first), None) => write!(f, ": only in the first object ({first:010X})"),
+            (None, Some(second)) => write!(f, ": only in the second object ({second:010X})"),
+            _ => {
+                let changes = self
+                    .changes
+                    .iter()
+                    .map(|change| format!("{} {}->{}", change.field, change.first, change.second))
+                    .collect::<Vec<_>>();
+                write!(f, ": {}", changes.join(", "))
+            }
+        }
+    }
+}
+
+/// Labels of an object by address
+type Labels<'a> = HashMap<MachineAddress, Vec<&'a str>>;
+
+/// Name of the address, with the first of `labels` placed there
+fn address_name(labels: &Labels, address: Option<MachineAddress>) -> String {
+    match address {
+        Some(address) => match labels.get(&address) {
+            Some(labels) => format!("{address:03X} ({})", labels[0]),
+            None => format!("{address:03X}"),
+        },
+        None => "-".to_string(),
+    }
+}
+
+/// Fields in which the words of `first` and `second` at `address` differ
+fn changes(
+    address: MachineAddress,
+    (first, first_code): (&Labels, MachineCode),
+    (second, second_code): (&Labels, MachineCode),
+) -> Vec<FieldChange> {
+    const NAMES: [&str; 9] = ["LB", "RB", "AL", "SH", "SB", "MM", "SQ", "TS", "EX"];
+    let first_fields = Fields::decode(address, first_code);
+    let second_fields = Fields::decode(address, second_code);
+    let name = |mnemonic: &str| match mnemonic {
+        "" => "-".to_string(),
+        mnemonic => mnemonic.to_string(),
+    };
+
+    let mut changes = NAMES
+        .iter()
+        .zip(
+            first_fields
+                .mnemonics()
+                .iter()
+                .zip(second_fields.mnemonics()),
+        )
+        .filter(|(_, (first, second))| *first != second)
+        .map(|(field, (first, second))| FieldChange {
+            field,
+            first: name(first),
+            second: name(second),
+        })
+        .collect::<Vec<_>>();
+    if first_fields.literal != second_fields.literal {
+        let literal =
+            |literal: Option<u16>| literal.map_or("-".to_string(), |l| format!("{l:04X}"));
+        changes.push(FieldChange {
+            field: "literal",
+            first: literal(first_fields.literal),
+            second: literal(second_fields.literal),
+        });
+    }
+    if first_fields.target != second_fields.target {
+        changes.push(FieldChange {
+            field: "branch target",
+            first: address_name(first, first_fields.target),
+            second: address_name(second, second_fields.target),
+        });
+    }
+    changes
+}
+
+/// Compares the words that `first` and `second` place at each address
+///
+/// Differences are listed by address. The fields are compared as decoded at the address, and
+/// labels are taken from the symbols of the objects, which an object read from a `CM` file
+/// does not have.
+pub fn diff(first: &Object, second: &Object) -> Vec<WordDifference> {
+    let mut words = BTreeMap::<MachineAddress, (Option<MachineCode>, Option<MachineCode>)>::new();
+    for &(address, code) in &first.code {
+        words.entry(address).or_default().0 = Some(code);
+    }
+    for &(address, code) in &second.code {
+        words.entry(address).or_default().1 = Some(code);
+    }
+
+    let first_labels = symbol::labels_by_address(&first.symbols);
+    let second_labels = symbol::labels_by_address(&second.symbols);
+    words
+        .into_iter()
+        .filter(|(_, (first, second))| first != second)
+        .map(|(address, (first_code, second_code))| {
+            let label = first_labels
+                .get(&address)
+                .or_else(|| second_labels.get(&address))
+                .map(|labels| labels[0].to_string());
+            let changes = match (first_code, second_code) {
+                (Some(first_code), Some(second_code)) => changes(
+                    address,
+                    (&first_labels, first_code),
+                    (&second_labels, second_code),
+                ),
+                _ => Vec::new(),
+            };
+            WordDifference {
+                address,
+                first: first_code,
+                second: second_code,
+                label,
+                changes,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff;
+    use crate::{assemble, read_object, Options};
+
+    #[test]
+    fn test_diff() {
+        let first =
+            read_object("CM A\n000  FFFFF8FE00\n040  FFFFF01E40\n041  AA1F0FFE01\n").unwrap();
+        let second =
+            read_object("CM B\n000  FFFFF8FE00\n040  FFFFF10241\n042  AA1F0FFE01\n").unwrap();
+        let differences = diff(&first, &second)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            differences,
+            [
+                "040: SQ B->BP, EX NEX->FLS, branch target 040->041",
+                "041: only in the first object (AA1F0FFE01)",
+                "042: only in the second object (AA1F0FFE01)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_with_labels() {
+        let source = ".TITLE A\n* FETCH: 000\n\tGOTO LOOP\n* LOOP: 010\n\tGOTO FETCH\n.END\n";
+        let first = assemble(source, &Options::default()).unwrap();
+        let second = read_object("CM B\n000  FFFFF01E11\n010  FFFFF8FE00\n").unwrap();
+        assert_eq!(
+            diff(&first, &second)[0].to_string(),
+            "000 FETCH: branch target 010 (LOOP)->011"
+        );
+    }
+}
@@ -0,0 +1,476 @@
+use crate::decode::*;
+use crate::error::DisassembleError;
+use crate::parser::{MachineAddress, MachineCode};
+use crate::{assemble, Object, Options};
+
+use std::collections::{BTreeMap, HashSet};
+
+const LBUS: [Option<&str>; 16] = [
+    Some("R0"),
+    Some("R1"),
+    Some("R2"),
+    Some("R3"),
+    Some("R4"),
+    Some("R5"),
+    Some("R6"),
+    Some("R7"),
+    Some("RB"),
+    Some("RBP"),
+    Some("PC"),
+    Some("IO"),
+    Some("MM"),
+    Some("IR"),
+    Some("FSR"),
+    Some("ZERO"),
+];
+const RBUS: [Option<&str>; 16] = [
+    Some("R0"),
+    Some("R1"),
+    Some("R2"),
+    Some("R3"),
+    Some("R4"),
+    Some("R5"),
+    Some("R6"),
+    Some("R7"),
+    Some("RA"),
+    Some("RAP"),
+    None,
+    None,
+    None,
+    None,
+    None,
+    Some("0"),
+];
+const SBUS: [Option<&str>; 16] = [
+    Some("R0"),
+    Some("R1"),
+    Some("R2"),
+    Some("R3"),
+    Some("R4"),
+    Some("R5"),
+    Some("R6"),
+    Some("R7"),
+    Some("RA"),
+    Some("RAP"),
+    Some("RB"),
+    Some("RBP"),
+    Some("PC"),
+    None,
+    None,
+    None,
+];
+const ALU: [Option<&str>; 8] = [
+    Some("+"),
+    Some("-"),
+    Some("AND"),
+    Some("OR"),
+    Some("XOR"),
+    Some("$"),
+    None,
+    Some("@"),
+];
+const SHIFT: [Option<&str>; 8] = [
+    Some("SLL"),
+    Some("SRL"),
+    Some("SLA"),
+    Some("SRA"),
+    Some("SNX"),
+    Some("SWP"),
+    None,
+    None,
+];
+const MEMORY: [Option<&str>; 4] = [Some("READ"), Some("WRITE"), None, None];
+const FLAG: [Option<&str>; 8] = [
+    Some("ZER"),
+    Some("NEG"),
+    Some("CRY"),
+    Some("OV"),
+    Some("T"),
+    Some("CZ"),
+    None,
+    None,
+];
+const EX: [Option<&str>; 16] = [
+    Some("C - 1"),
+    Some("FLAG SAVE"),
+    Some("WITH CRY"),
+    Some("WITH ONE"),
+    Some("IR := LBUS"),
+    Some("IO := LBUS"),
+    Some("C := RBUS"),
+    Some("EXECUTE IO"),
+    Some("T := 1"),
+    Some("T := 0"),
+    Some("IRA + 1"),
+    Some("IRB + 1"),
+    Some("IRB - 1"),
+    Some("SET HLT"),
+    Some("SET OV"),
+    None,
+];
+
+/// Name given to the word at `address` when something branches to it
+fn label(address: MachineAddress) -> String {
+    format!("L{address:03X}")
+}
+
+/// Writes a literal so that it lexes as a number
+fn literal(value: u16) -> String {
+    let digits = format!("{value:X}");
+    if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits
+    } else {
+        format!("0{digits}")
+    }
+}
+
+/// Whether the word lets execution continue with the next address
+fn falls_through(fields: &Fields) -> bool {
+    !matches!(fields.sq, SQ_B | SQ_RTN | SQ_EI | SQ_IOP | SQ_IRA | SQ_IAB)
+}
+
+/// Source statements of one word
+struct Statements {
+    test_and_sequence: Option<String>,
+    memory: Option<&'static str>,
+    calculation: Option<String>,
+    ex: Option<String>,
+}
+
+/// Word being turned back into source
+struct Word {
+    address: MachineAddress,
+    code: MachineCode,
+    fields: Fields,
+}
+
+impl Word {
+    fn invalid(&self, field: &'static str) -> DisassembleError {
+        DisassembleError::InvalidField {
+            address: self.address,
+            code: self.code,
+            field,
+        }
+    }
+
+    fn name(
+        &self,
+        table: &[Option<&'static str>],
+        value: u8,
+        field: &'static str,
+    ) -> Result<&'static str, DisassembleError> {
+        table[usize::from(value)].ok_or_else(|| self.invalid(field))
+    }
+
+    fn lbus(&self) -> Result<&'static str, DisassembleError> {
+        self.name(&LBUS, self.fields.lb, "LB")
+    }
+
+    fn rbus(&self) -> Result<String, DisassembleError> {
+        match self.fields.rb {
+            RB_SLT | RB_LLT => Ok(literal(self.fields.literal.unwrap())),
+            rb => self.name(&RBUS, rb, "RB").map(str::to_string),
+        }
+    }
+
+    fn flag(&self) -> Result<&'static str, DisassembleError> {
+        self.name(&FLAG, self.fields.ts.unwrap_or(TS_NTS), "TS")
+    }
+
+    fn target(&self) -> Result<String, DisassembleError> {
+        self.fields
+            .target
+            .map(label)
+            .ok_or(DisassembleError::Unrepresentable {
+                address: self.address,
+                code: self.code,
+            })
+    }
+
+    fn statements(&self) -> Result<Statements, DisassembleError> {
+        let fields = &self.fields;
+
+        let test_and_sequence = match fields.sq {
+            SQ_B => Some(format!("GOTO {}", self.target()?)),
+            SQ_BP => Some(format!("CALL {}", self.target()?)),
+            SQ_RTN => Some("RETURN".to_string()),
+            SQ_BT => Some(format!("IF {} = 1 THEN {}", self.flag()?, self.target()?)),
+            SQ_BF => Some(format!("IF {} = 0 THEN {}", self.flag()?, self.target()?)),
+            SQ_EI if fields.ts.unwrap_or(TS_NTS) == TS_NTS => Some("GOTO FETCH".to_string()),
+            SQ_EI => Some(format!(
+                "IF {} = 0 THEN {} ELSE FETCH",
+                self.flag()?,
+                self.target()?
+            )),
+            SQ_IOP => Some(format!("IOP {}", self.target()?)),
+            SQ_IRA => Some(format!("IRA {}", self.target()?)),
+            SQ_IAB => Some(format!("IAB {}", self.target()?)),
+            SQ_NSQ => None,
+            _ => return Err(self.invalid("SQ")),
+        };
+
+        let memory = match fields.mm {
+            MM_NMM => None,
+            mm => Some(self.name(&MEMORY, mm, "MM")?),
+        };
+
+        let ex = fields.ex.unwrap_or(EX_NEX);
+        let quiet = fields.al == AL_NAL && fields.sh == SH_NSH && fields.sb == SB_NSB;
+        // `IR :=`, `IO :=` and `C :=` with a source drive the bus without a calculation
+        let (calculation, ex) = match ex {
+            EX_LIR | EX_LIO if quiet && fields.rb == RB_NRB && fields.lb != LB_NLB => {
+                let register = if ex == EX_LIR { "IR" } else { "IO" };
+                (None, Some(format!("{register} := {}", self.lbus()?)))
+            }
+            EX_SC if quiet && fields.lb == LB_NLB && fields.rb != RB_NRB => {
+                (None, Some(format!("C := {}", self.rbus()?)))
+            }
+            EX_NEX => (self.calculation()?, None),
+            ex => (
+                self.calculation()?,
+                Some(self.name(&EX, ex, "EX")?.to_string()),
+            ),
+        };
+
+        Ok(Statements {
+            test_and_sequence,
+            memory,
+            calculation,
+            ex,
+        })
+    }
+
+    fn calculation(&self) -> Result<Option<String>, DisassembleError> {
+        let fields = &self.fields;
+        if fields.lb == LB_NLB
+            && fields.rb == RB_NRB
+            && fields.al == AL_NAL
+            && fields.sh == SH_NSH
+            && fields.sb == SB_NSB
+        {
+            return Ok(None);
+        }
+
+        // the ALU function that passes a bus through unchanged
+        let (destination, through) = match fields.sb {
+            SB_NSB => ("SET BY".to_string(), AL_OR),
+            sb => (format!("{} :=", self.name(&SBUS, sb, "SB")?), AL_NAL),
+        };
+        let shift = match fields.sh {
+            SH_NSH => None,
+            sh => Some(self.name(&SHIFT, sh, "SH")?),
+        };
+        let lbus = self.lbus()?;
+        let rbus = self.rbus()?;
+        // a register named on its own is taken to be on the Lbus
+        let rbus_only = matches!(fields.rb, RB_RA | RB_RAP | RB_SLT | RB_LLT);
+
+        let expression = if fields.al == through && fields.rb == RB_NRB {
+            format!(
+                "{}{lbus}",
+                shift.map_or(String::new(), |shift| format!("{shift} "))
+            )
+        } else if fields.al == through && fields.lb == LB_NLB && rbus_only {
+            format!(
+                "{}{rbus}",
+                shift.map_or(String::new(), |shift| format!("{shift} "))
+            )
+        } else {
+            format!(
+                "{lbus} {}{} {rbus}",
+                self.name(&ALU, fields.al, "AL")?,
+                shift.map_or(String::new(), |shift| format!(":{shift}"))
+            )
+        };
+
+        Ok(Some(format!("{destination} {expression}")))
+    }
+}
+
+/// Writes source that assembles back to `object`
+///
+/// Words are written in address order. Branch targets get labels named after their address,
+/// and every instruction that does not follow a fall-through from the previous address gets an
+/// explicit address. The source is assembled again and every word that does not come back
+/// identical is reported.
+pub fn disassemble(object: &Object) -> Result<String, Vec<DisassembleError>> {
+    let words = object.code.iter().copied().collect::<BTreeMap<_, _>>();
+    let decoded = words
+        .iter()
+        .map(|(&address, &code)| Word {
+            address,
+            code,
+            fields: Fields::decode(address, code),
+        })
+        .collect::<Vec<_>>();
+
+    let mut errors = Vec::<DisassembleError>::new();
+    let mut targets = HashSet::<MachineAddress>::new();
+    for word in &decoded {
+        let target = match word.fields.sq {
+            SQ_B | SQ_BP | SQ_BT | SQ_BF | SQ_EI | SQ_IOP | SQ_IRA | SQ_IAB => word.fields.target,
+            _ => None,
+        };
+        if let Some(target) = target {
+            if words.contains_key(&target) {
+                targets.insert(target);
+            } else {
+                errors.push(DisassembleError::MissingTarget {
+                    address: word.address,
+                    code: word.code,
+                    target,
+                });
+            }
+        }
+    }
+
+    let mut source = format!(".TITLE {}\n", object.title);
+    let mut previous: Option<&Word> = None;
+    for word in &decoded {
+        let statements = match word.statements() {
+            Ok(statements) => statements,
+            Err(error) => {
+                errors.push(error);
+                continue;
+            }
+        };
+
+        let continued = matches!(
+            previous,
+            Some(previous) if previous.address + 1 == word.address
+                && falls_through(&previous.fields)
+        );
+        source += "*";
+        if targets.contains(&word.address) {
+            source += &format!(" {}:", label(word.address));
+        }
+        if !continued {
+            source += &format!(" {:03X}", word.address);
+        }
+        source += "\n";
+        for statement in [
+            statements.test_and_sequence,
+            statements.memory.map(str::to_string),
+            statements.calculation,
+            statements.ex,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            source += &format!("\t{statement}\n");
+        }
+
+        previous = Some(word);
+    }
+    source += ".END\n";
+
+    if errors.is_empty() {
+        let assembled = assemble(&source, &Options::default())
+            .map(|object| object.code.into_iter().collect::<BTreeMap<_, _>>())
+            .unwrap_or_default();
+        errors.extend(
+            words
+                .iter()
+                .filter(|(address, code)| assembled.get(address) != Some(code))
+                .map(|(&address, &code)| DisassembleError::Unrepresentable { address, code }),
+        );
+    }
+
+    if errors.is_empty() {
+        Ok(source)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble;
+    use crate::error::DisassembleError;
+    use crate::{assemble, read_object, Options};
+
+    const SOURCE: &str = "\
+.TITLE ROUNDTRIP
+* FETCH: 000
+\tREAD
+\tPC := PC + 1
+*
+\tIR := MM
+*
+\tIOP OPS
+* OPS: 020
+\tIRA MODES
+*
+\tIAB BOTH
+*
+\tSET BY R1 AND:SLL R2
+*
+\tRA := SRL RAP
+\tIRA + 1
+*
+\tR3 := 1FF
+*
+\tC := 12
+*
+\tIO := PC
+*
+\tSET BY 0ABCD
+*
+\tR4 := R5 $ 0
+\tFLAG SAVE
+*
+\tWRITE
+\tRBP := ZERO @ R6
+\tSET HLT
+*
+\tGOTO FETCH
+* MODES: 040
+\tIF NEG = 0 THEN MODES ELSE FETCH
+\tC := RBUS
+* BOTH: 080
+\tIF CZ = 0 THEN BOTH
+\tR7 := IR XOR:SWP R0
+\tT := 1
+*
+\tCALL SUB
+\tEXECUTE IO
+*
+\tRETURN
+* SUB:
+\tRETURN
+\tSET BY FSR
+.END
+";
+
+    #[test]
+    fn test_round_trip() {
+        let object = assemble(SOURCE, &Options::default()).unwrap();
+        let source = disassemble(&object).unwrap();
+        assert!(source.contains("* L020: 020\n\tIRA L040\n"));
+        assert!(source.contains("\tIF NEG = 0 THEN L040 ELSE FETCH\n"));
+        assert_eq!(
+            assemble(&source, &Options::default()).unwrap().code,
+            object.code
+        );
+    }
+
+    #[test]
+    fn test_invalid_word() {
+        let object = read_object("CM BROKEN\n000  FFFFF9FE00\n001  FFFFF01E10\n").unwrap();
+        assert_eq!(
+            disassemble(&object),
+            Err(vec![
+                DisassembleError::MissingTarget {
+                    address: 0x001,
+                    code: 0xFFFFF01E10,
+                    target: 0x010,
+                },
+                DisassembleError::InvalidField {
+                    address: 0x000,
+                    code: 0xFFFFF9FE00,
+                    field: "SQ",
+                },
+            ])
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::error::EncodingError;
+
+use std::str::FromStr;
+
+/// Character encoding of source programs and object files
+///
+/// The reference sources of MICRO-1 are Shift_JIS, with Japanese in their comments.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    ShiftJis,
+    EucJp,
+}
+
+impl Encoding {
+    /// Names accepted by [`Encoding::from_str`], each followed by its aliases
+    pub const NAMES: [&'static str; 7] = [
+        "utf-8",
+        "utf8",
+        "shift_jis",
+        "sjis",
+        "cp932",
+        "euc-jp",
+        "eucjp",
+    ];
+
+    pub fn name(self) -> &'static str {
+        self.encoding().name()
+    }
+
+    fn encoding(self) -> &'static encoding_rs::Encoding {
+        match self {
+            Encoding::Utf8 => encoding_rs::UTF_8,
+            Encoding::ShiftJis => encoding_rs::SHIFT_JIS,
+            Encoding::EucJp => encoding_rs::EUC_JP,
+        }
+    }
+
+    /// Guesses the encoding of `bytes`
+    ///
+    /// UTF-8 is tried first, then EUC-JP and Shift_JIS: Shift_JIS text is rarely valid EUC-JP,
+    /// while EUC-JP text mostly reads as half-width katakana in Shift_JIS.
+    pub fn detect(bytes: &[u8]) -> Result<Self, EncodingError> {
+        [Encoding::Utf8, Encoding::EucJp, Encoding::ShiftJis]
+            .into_iter()
+            .find(|encoding| encoding.decode(bytes).is_ok())
+            .ok_or_else(|| match Encoding::Utf8.decode(bytes) {
+                Err(EncodingError::Malformed { line, .. }) => EncodingError::Undetected { line },
+                _ => unreachable!(),
+            })
+    }
+
+    /// Converts `bytes` to text, failing at the first malformed sequence
+    pub fn decode(self, bytes: &[u8]) -> Result<String, EncodingError> {
+        let mut decoder = self.encoding().new_decoder_without_bom_handling();
+        let mut text = String::with_capacity(
+            decoder
+                .max_utf8_buffer_length_without_replacement(bytes.len())
+                .unwrap(),
+        );
+        match decoder.decode_to_string_without_replacement(bytes, &mut text, true) {
+            (encoding_rs::DecoderResult::InputEmpty, _) => Ok(text),
+            (encoding_rs::DecoderResult::Malformed(..), _) => Err(EncodingError::Malformed {
+                encoding: self.name(),
+                line: text.matches('\n').count() + 1,
+            }),
+            (encoding_rs::DecoderResult::OutputFull, _) => unreachable!(),
+        }
+    }
+
+    /// Converts `text` to bytes, failing at the first character without a code
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, EncodingError> {
+        let (bytes, _, unmappable) = self.encoding().encode(text);
+        if !unmappable {
+            return Ok(bytes.into_owned());
+        }
+        let mut encoder = self.encoding().new_encoder();
+        let mut bytes = Vec::with_capacity(
+            encoder
+                .max_buffer_length_from_utf8_without_replacement(text.len())
+                .unwrap(),
+        );
+        match encoder.encode_from_utf8_to_vec_without_replacement(text, &mut bytes, true) {
+            (encoding_rs::EncoderResult::Unmappable(character), _) => {
+                Err(EncodingError::Unmappable {
+                    encoding: self.name(),
+                    character,
+                })
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "shift_jis" | "sjis" | "cp932" => Ok(Encoding::ShiftJis),
+            "euc-jp" | "eucjp" => Ok(Encoding::EucJp),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Encoding;
+    use crate::error::EncodingError;
+
+    // "; 加算" in each encoding
+    const UTF_8: &[u8] = b"; \xE5\x8A\xA0\xE7\xAE\x97\n";
+    const SHIFT_JIS: &[u8] = b"; \x89\xC1\x8E\x5A\n";
+    const EUC_JP: &[u8] = b"; \xB2\xC3\xBB\xBB\n";
+
+    #[test]
+    fn test_from_str() {
+        for name in Encoding::NAMES {
+            assert!(name.parse::<Encoding>().is_ok(), "{name}");
+        }
+        assert_eq!("SJIS".parse(), Ok(Encoding::ShiftJis));
+        assert_eq!("latin1".parse::<Encoding>(), Err(()));
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Encoding::detect(UTF_8), Ok(Encoding::Utf8));
+        assert_eq!(Encoding::detect(SHIFT_JIS), Ok(Encoding::ShiftJis));
+        assert_eq!(Encoding::detect(EUC_JP), Ok(Encoding::EucJp));
+        assert_eq!(
+            Encoding::detect(b".TITLE T\n\xFF\xFF\n"),
+            Err(EncodingError::Undetected { line: 2 })
+        );
+    }
+
+    #[test]
+    fn test_decode_and_encode() {
+        for (encoding, bytes) in [
+            (Encoding::Utf8, UTF_8),
+            (Encoding::ShiftJis, SHIFT_JIS),
+            (Encoding::EucJp, EUC_JP),
+        ] {
+            let text = encoding.decode(bytes).unwrap();
+            assert_eq!(text, "; 加算\n");
+            assert_eq!(encoding.encode(&text).unwrap(), bytes);
+        }
+        assert_eq!(
+            Encoding::Utf8.decode(SHIFT_JIS),
+            Err(EncodingError::Malformed {
+                encoding: "UTF-8",
+                line: 1
+            })
+        );
+        assert_eq!(
+            Encoding::ShiftJis.encode("CM \u{1F600}"),
+            Err(EncodingError::Unmappable {
+                encoding: "Shift_JIS",
+                character: '\u{1F600}'
+            })
+        );
+    }
+}
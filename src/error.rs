@@ -0,0 +1,792 @@
+use crate::include::SourceMap;
+use crate::parser::{MachineAddress, MachineCode};
+use crate::placement::MAX_ADDRESS;
+use crate::symbol::ReferenceKind;
+
+use ariadne::{Cache, Color, Fmt, Label, Report, ReportKind};
+use logos::Span;
+
+use std::fmt;
+use std::io;
+
+/// Identifies the instruction an error was found in
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Location {
+    pub label: Option<String>,
+    /// Span of the instruction head (`* LABEL: ADDRESS`)
+    pub span: Span,
+    pub address: Option<MachineAddress>,
+}
+
+/// Converts a span in bytes of `source`, as the lexer gives it, to one in characters, as
+/// reports are drawn
+fn chars(source: &str, span: &Span) -> Span {
+    let start = source[..span.start].chars().count();
+    start..start + source[span.clone()].chars().count()
+}
+
+/// Names the file a span of the assembled source was written in, with the span in characters
+/// of that file
+type Locate<'a> = dyn Fn(&Span) -> (String, Span) + 'a;
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.label, self.address) {
+            (Some(label), Some(address)) => write!(f, "{label} ({address:03X})"),
+            (Some(label), None) => write!(f, "{label}"),
+            (None, Some(address)) => write!(f, "{address:03X}"),
+            (None, None) => write!(f, "instruction"),
+        }
+    }
+}
+
+/// Field that keeps a long literal out of the 16 LSBs
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Conflict {
+    Reference,
+    Test,
+    Ex,
+}
+
+/// Register latched from the Lbus by an EX statement
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LbusDestination {
+    Ir,
+    Io,
+}
+
+/// Reason why an instruction cannot be encoded
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EncodeError {
+    /// LLT fills the 16 LSBs, which another field needs too
+    LongLiteral(Conflict),
+    /// SLT shares the 9 LSBs with a branch address
+    ShortLiteral,
+    /// `IR :=` or `IO :=` names a source while a calculation statement drives the Lbus
+    LbusSource(LbusDestination),
+    /// `C :=` names a source while a calculation statement drives the Rbus
+    RbusSource,
+    UnresolvedReference(String),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::LongLiteral(conflict) => write!(
+                f,
+                "a long literal occupies the 16 LSBs, which {} also needs",
+                match conflict {
+                    Conflict::Reference => "the branch address",
+                    Conflict::Test => "the TS field",
+                    Conflict::Ex => "the EX field",
+                }
+            ),
+            EncodeError::ShortLiteral => write!(
+                f,
+                "a short literal occupies the 9 LSBs, which the branch address also needs"
+            ),
+            EncodeError::LbusSource(destination) => write!(
+                f,
+                "`{} :=` cannot name a source while the calculation statement drives the Lbus",
+                match destination {
+                    LbusDestination::Ir => "IR",
+                    LbusDestination::Io => "IO",
+                }
+            ),
+            EncodeError::RbusSource => write!(
+                f,
+                "`C :=` cannot name a source while the calculation statement drives the Rbus"
+            ),
+            EncodeError::UnresolvedReference(symbol) => write!(f, "{symbol} is not defined"),
+        }
+    }
+}
+
+/// Reason why an `.INCLUDE` line cannot be spliced
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum IncludeError {
+    /// The line is not `.INCLUDE` followed by a quoted file name
+    Malformed,
+    /// No file of the name is next to the including file or in the include paths
+    NotFound,
+    /// The file is already being included
+    Cycle,
+    Unreadable(String),
+}
+
+impl fmt::Display for IncludeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IncludeError::Malformed => write!(f, "expected a file name in double quotes"),
+            IncludeError::NotFound => write!(f, "no such file in the include paths"),
+            IncludeError::Cycle => write!(f, "the file is already being included"),
+            IncludeError::Unreadable(why) => write!(f, "{why}"),
+        }
+    }
+}
+
+/// Reason why an expression has no value
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ExpressionError {
+    /// The value does not fit in `0..=max`
+    OutOfRange {
+        value: i64,
+        max: u16,
+    },
+    DivisionByZero,
+    /// A step of the calculation is too large to be carried out
+    Overflow,
+    /// A label is used in an address, which is needed before labels have addresses
+    LabelInAddress(String),
+    /// A label is used in the value of a constant, which is needed before parsing
+    LabelInConstant(String),
+    UnresolvedLabel(String),
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpressionError::OutOfRange { value, .. } if *value < 0 => {
+                write!(f, "the value is negative (-{:X})", value.unsigned_abs())
+            }
+            ExpressionError::OutOfRange { value, max } => {
+                write!(f, "the value {value:X} is beyond {max:X}")
+            }
+            ExpressionError::DivisionByZero => write!(f, "division by zero"),
+            ExpressionError::Overflow => write!(f, "the calculation overflows"),
+            ExpressionError::LabelInAddress(label) => write!(
+                f,
+                "{label} is a label, and an address is calculated from numbers and constants only"
+            ),
+            ExpressionError::LabelInConstant(label) => write!(
+                f,
+                "{label} is a label, and a constant is calculated from numbers and constants \
+                 defined before it only"
+            ),
+            ExpressionError::UnresolvedLabel(label) => write!(f, "{label} is not defined"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    InvalidToken {
+        span: Span,
+    },
+    UnexpectedToken {
+        span: Span,
+    },
+    /// The fixed address of an IOP, IRA or IAB target has bits of `mask` set
+    MisalignedAddress {
+        location: Location,
+        kind: ReferenceKind,
+        mask: MachineAddress,
+    },
+    /// Two instructions are placed at the same address
+    AddressCollision {
+        location: Location,
+        /// The instruction placed there first
+        previous: Location,
+    },
+    /// The address does not fit in the 12-bit control store
+    AddressOutOfRange {
+        location: Location,
+    },
+    /// No free space is left for a block of consecutive instructions
+    Unplaceable {
+        location: Location,
+        words: usize,
+        /// `(offset, mask)` of each dispatch target in the block
+        alignment: Vec<(usize, MachineAddress)>,
+    },
+    /// A conditional branch cannot reach a target in another 512-word page
+    UnreachableTarget {
+        location: Location,
+        /// Span of the branching statement
+        span: Span,
+        symbol: String,
+        target: MachineAddress,
+    },
+    DuplicateLabel {
+        label: String,
+        span: Span,
+        /// Span of the first definition
+        previous: Span,
+    },
+    UnresolvedSymbol {
+        location: Location,
+        /// Span of the referencing statement
+        span: Span,
+        symbol: String,
+    },
+    DuplicateConstant {
+        name: String,
+        span: Span,
+        /// Span of the first definition, or `None` if it was given on the command line
+        previous: Option<Span>,
+    },
+    /// The constant is defined but never used
+    UnusedConstant {
+        name: String,
+        /// Span of the definition, or `None` if it was given on the command line
+        span: Option<Span>,
+    },
+    Encoding {
+        location: Location,
+        /// Span of the statement that cannot be encoded
+        span: Span,
+        reason: EncodeError,
+    },
+    /// An `.INCLUDE` line names a file that cannot be spliced in
+    Include {
+        /// Span of the `.INCLUDE` line
+        span: Span,
+        path: String,
+        reason: IncludeError,
+    },
+    /// An expression in a literal or an address has no value
+    Expression {
+        /// Span of the expression, or of the part of it that fails
+        span: Span,
+        reason: ExpressionError,
+    },
+}
+
+impl Error {
+    pub fn span(&self) -> Span {
+        match self {
+            Error::InvalidToken { span }
+            | Error::UnexpectedToken { span }
+            | Error::UnreachableTarget { span, .. }
+            | Error::DuplicateLabel { span, .. }
+            | Error::UnresolvedSymbol { span, .. }
+            | Error::DuplicateConstant { span, .. }
+            | Error::Encoding { span, .. }
+            | Error::Include { span, .. }
+            | Error::Expression { span, .. } => span.clone(),
+            // a constant defined on the command line is reported at the start of the program
+            Error::UnusedConstant { span, .. } => span.clone().unwrap_or(0..0),
+            Error::MisalignedAddress { location, .. }
+            | Error::AddressCollision { location, .. }
+            | Error::AddressOutOfRange { location }
+            | Error::Unplaceable { location, .. } => location.span.clone(),
+        }
+    }
+
+    fn report(&self, locate: &Locate, source: &str) -> Report<(String, Span)> {
+        let span = self.span();
+        let (path, located) = locate(&span);
+        let label = Label::new((path.clone(), located.clone()));
+        let label = match self {
+            Error::InvalidToken { span } => label.with_message(format!(
+                "{} is not a valid token",
+                &source[span.clone()].fg(Color::Red)
+            )),
+            Error::UnexpectedToken { span } => label.with_message(format!(
+                "Unexpected token {}",
+                &source[span.clone()].fg(Color::Red)
+            )),
+            Error::MisalignedAddress { kind, mask, .. } => label.with_message(format!(
+                "{} targets need address bits {:03X} cleared",
+                kind.fg(Color::Red),
+                mask
+            )),
+            Error::AddressCollision { location, .. } => label.with_message(format!(
+                "{} is placed at an occupied address",
+                location.fg(Color::Red)
+            )),
+            Error::AddressOutOfRange { location } => label.with_message(format!(
+                "{} is placed beyond {:03X}",
+                location.fg(Color::Red),
+                MAX_ADDRESS
+            )),
+            Error::Unplaceable { words, alignment, .. } => label.with_message(format!(
+                "no free run of {} word{}{} is left in the control store",
+                words.fg(Color::Red),
+                if *words == 1 { "" } else { "s" },
+                alignment
+                    .iter()
+                    .map(|(offset, mask)| format!(
+                        " with bits {mask:03X} of word {offset} cleared"
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(" and")
+            )),
+            Error::UnreachableTarget { symbol, target, .. } => label.with_message(format!(
+                "{} ({target:03X}) is not in the page of this branch, which only encodes the 9 LSBs",
+                symbol.fg(Color::Red)
+            )),
+            Error::DuplicateLabel { label: name, .. } | Error::DuplicateConstant { name, .. } => {
+                label.with_message(format!("{} is redefined here", name.fg(Color::Red)))
+            }
+            Error::UnresolvedSymbol { symbol, .. } => {
+                label.with_message(format!("{} is not defined", symbol.fg(Color::Red)))
+            }
+            Error::UnusedConstant { name, .. } => {
+                label.with_message(format!("{} is never used", name.fg(Color::Red)))
+            }
+            Error::Encoding { reason, .. } => label.with_message(reason),
+            Error::Include { reason, .. } => label.with_message(reason),
+            Error::Expression { reason, .. } => label.with_message(reason),
+        };
+
+        let report = Report::build(ReportKind::Error, path, located.start)
+            .with_message(self)
+            .with_label(label);
+        match self {
+            Error::InvalidToken { .. }
+            | Error::UnexpectedToken { .. }
+            | Error::Include { .. }
+            | Error::Expression { .. } => report,
+            Error::DuplicateLabel { previous, .. }
+            | Error::DuplicateConstant {
+                previous: Some(previous),
+                ..
+            } => report.with_label(Label::new(locate(previous)).with_message("first defined here")),
+            Error::DuplicateConstant { previous: None, .. } => {
+                report.with_note("it is first defined on the command line")
+            }
+            Error::UnusedConstant { span: Some(_), .. } => report,
+            Error::UnusedConstant { span: None, .. } => {
+                report.with_note("it is defined on the command line")
+            }
+            Error::AddressCollision { previous, .. } => report.with_label(
+                Label::new(locate(&previous.span))
+                    .with_message(format!("{previous} is placed there first")),
+            ),
+            Error::AddressOutOfRange { .. } => report,
+            Error::Unplaceable { .. } => report.with_note(
+                "instructions that fall through, and the jump table of a dispatch target, \
+                 must be placed at consecutive addresses",
+            ),
+            Error::MisalignedAddress { location, .. }
+            | Error::UnreachableTarget { location, .. }
+            | Error::UnresolvedSymbol { location, .. }
+            | Error::Encoding { location, .. } => {
+                report.with_note(format!("in instruction {location}"))
+            }
+        }
+        .finish()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidToken { .. } => write!(f, "Invalid token"),
+            Error::UnexpectedToken { .. } => write!(f, "Unexpected token"),
+            Error::MisalignedAddress { location, kind, .. } => {
+                write!(f, "Misaligned {kind} target {location}")
+            }
+            Error::AddressCollision { location, .. } => {
+                write!(f, "Address collision at {location}")
+            }
+            Error::AddressOutOfRange { location } => {
+                write!(f, "Address out of range {location}")
+            }
+            Error::Unplaceable { location, .. } => write!(f, "Cannot place {location}"),
+            Error::UnreachableTarget { symbol, .. } => {
+                write!(f, "Branch target {symbol} is out of reach")
+            }
+            Error::DuplicateLabel { label, .. } => write!(f, "Duplicate label {label}"),
+            Error::UnresolvedSymbol { symbol, .. } => write!(f, "Unresolved symbol {symbol}"),
+            Error::DuplicateConstant { name, .. } => write!(f, "Duplicate constant {name}"),
+            Error::UnusedConstant { name, .. } => write!(f, "Unused constant {name}"),
+            Error::Encoding { reason, .. } => write!(f, "Illegal microinstruction: {reason}"),
+            Error::Include { path, .. } if path.is_empty() => write!(f, "Malformed include"),
+            Error::Include { path, .. } => write!(f, "Cannot include {path}"),
+            Error::Expression { .. } => write!(f, "Invalid expression"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Suspicious code that still assembles
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Warning {
+    /// `FETCH` is defined away from 000, where `GOTO FETCH` always goes
+    FetchLabel { span: Span, address: MachineAddress },
+    /// The label is spelled like a keyword
+    KeywordLabel { span: Span, label: String },
+    /// The label is spelled like a register
+    RegisterLabel { span: Span, label: String },
+    /// The label reads as a hexadecimal number where a number is expected
+    HexadecimalLabel { span: Span, label: String },
+}
+
+impl Warning {
+    pub fn span(&self) -> Span {
+        match self {
+            Warning::FetchLabel { span, .. }
+            | Warning::KeywordLabel { span, .. }
+            | Warning::RegisterLabel { span, .. }
+            | Warning::HexadecimalLabel { span, .. } => span.clone(),
+        }
+    }
+
+    fn report(&self, locate: &Locate) -> Report<(String, Span)> {
+        let (path, span) = locate(&self.span());
+        let label = Label::new((path.clone(), span.clone())).with_color(Color::Yellow);
+        let label = match self {
+            Warning::FetchLabel { address, .. } => label.with_message(format!(
+                "{} is assembled as EI, which branches to 000 instead of {address:03X}",
+                "GOTO FETCH".fg(Color::Yellow)
+            )),
+            Warning::KeywordLabel { label: name, .. } => label.with_message(format!(
+                "{} is a keyword of the micro assembly language",
+                name.fg(Color::Yellow)
+            )),
+            Warning::RegisterLabel { label: name, .. } => label.with_message(format!(
+                "{} is the name of a register",
+                name.fg(Color::Yellow)
+            )),
+            Warning::HexadecimalLabel { label: name, .. } => label.with_message(format!(
+                "{} is read as a hexadecimal number where a number is expected",
+                name.fg(Color::Yellow)
+            )),
+        };
+
+        Report::build(ReportKind::Warning, path, span.start)
+            .with_message(self)
+            .with_label(label)
+            .finish()
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::FetchLabel { .. } => write!(f, "Label FETCH is not at address 000"),
+            Warning::KeywordLabel { label, .. } => write!(f, "Label {label} is a keyword"),
+            Warning::RegisterLabel { label, .. } => write!(f, "Label {label} is a register name"),
+            Warning::HexadecimalLabel { label, .. } => {
+                write!(f, "Label {label} looks like a hexadecimal number")
+            }
+        }
+    }
+}
+
+/// Errors and warnings found while assembling a source program
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Diagnostics {
+    errors: Vec<Error>,
+    warnings: Vec<Warning>,
+}
+
+impl Diagnostics {
+    /// Collects errors and warnings in the order they appear in the source
+    pub(crate) fn new(mut errors: Vec<Error>, mut warnings: Vec<Warning>) -> Self {
+        errors.sort_by_key(|error| error.span().start);
+        warnings.sort_by_key(|warning| warning.span().start);
+        Diagnostics { errors, warnings }
+    }
+
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty() && self.warnings.is_empty()
+    }
+
+    /// Prints every error and warning to stderr, pointing at the offending part of `source`
+    pub fn eprint(&self, path: &str, source: &str) -> io::Result<()> {
+        self.eprint_located(
+            source,
+            &|span| (path.to_string(), chars(source, span)),
+            ariadne::sources([(path.to_string(), source)]),
+        )
+    }
+
+    /// Prints every error and warning to stderr, pointing at the file of `map` that the
+    /// offending part was written in
+    pub fn eprint_map(&self, map: &SourceMap) -> io::Result<()> {
+        self.eprint_located(
+            map.text(),
+            &|span| {
+                let (file, span) = map.locate(span);
+                (file.path.display().to_string(), chars(&file.text, &span))
+            },
+            ariadne::sources(
+                map.files()
+                    .iter()
+                    .map(|file| (file.path.display().to_string(), file.text.as_str())),
+            ),
+        )
+    }
+
+    fn eprint_located(
+        &self,
+        source: &str,
+        locate: &Locate,
+        mut cache: impl Cache<String>,
+    ) -> io::Result<()> {
+        let mut reports = self
+            .errors
+            .iter()
+            .map(|error| (error.span().start, error.report(locate, source)))
+            .chain(
+                self.warnings
+                    .iter()
+                    .map(|warning| (warning.span().start, warning.report(locate))),
+            )
+            .collect::<Vec<_>>();
+        reports.sort_by_key(|(start, _)| *start);
+
+        for (_, report) in reports {
+            report.eprint(&mut cache)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Error> for Diagnostics {
+    fn from(error: Error) -> Self {
+        Diagnostics::new(vec![error], Vec::new())
+    }
+}
+
+impl FromIterator<Error> for Diagnostics {
+    fn from_iter<I: IntoIterator<Item = Error>>(iter: I) -> Self {
+        Diagnostics::new(iter.into_iter().collect(), Vec::new())
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, error) in self.errors.iter().enumerate() {
+            if index != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+/// Reason why a `CM` object cannot be read
+///
+/// Lines and columns count from 1.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ReadError {
+    /// The first line is not `CM` followed by the title
+    MissingHeader,
+    /// The line is not an address followed by a word
+    MalformedLine { line: usize },
+    /// The address or the word is not hexadecimal
+    InvalidHex {
+        line: usize,
+        column: usize,
+        field: &'static str,
+    },
+    /// The address does not have 3 digits, or the word does not have 10
+    FieldWidth {
+        line: usize,
+        column: usize,
+        field: &'static str,
+        digits: usize,
+        expected: usize,
+    },
+    /// The address is beyond the control store
+    AddressOutOfRange {
+        line: usize,
+        column: usize,
+        address: u32,
+    },
+    /// A word was already given for the address
+    DuplicateAddress {
+        line: usize,
+        address: MachineAddress,
+        previous: usize,
+    },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::MissingHeader => write!(f, "The object does not start with `CM`"),
+            ReadError::MalformedLine { line } => {
+                write!(f, "Line {line} is not an address followed by a word")
+            }
+            ReadError::InvalidHex {
+                line,
+                column,
+                field,
+            } => write!(f, "Line {line}, column {column}: the {field} is not hexadecimal"),
+            ReadError::FieldWidth {
+                line,
+                column,
+                field,
+                digits,
+                expected,
+            } => write!(
+                f,
+                "Line {line}, column {column}: the {field} has {digits} digits instead of {expected}"
+            ),
+            ReadError::AddressOutOfRange {
+                line,
+                column,
+                address,
+            } => write!(
+                f,
+                "Line {line}, column {column}: the address {address:X} is beyond {MAX_ADDRESS:03X}"
+            ),
+            ReadError::DuplicateAddress {
+                line,
+                address,
+                previous,
+            } => write!(
+                f,
+                "Line {line}: the address {address:03X} already has a word on line {previous}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// Reason why text cannot be converted from or to an [`Encoding`](crate::Encoding)
+///
+/// Lines count from 1.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EncodingError {
+    /// The bytes are not valid in the encoding
+    Malformed { encoding: &'static str, line: usize },
+    /// The bytes are not valid in any of the encodings that are told apart
+    Undetected { line: usize },
+    /// The character has no code in the encoding
+    Unmappable {
+        encoding: &'static str,
+        character: char,
+    },
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodingError::Malformed { encoding, line } => {
+                write!(f, "Line {line} is not valid {encoding}")
+            }
+            EncodingError::Undetected { line } => write!(
+                f,
+                "Line {line} is not valid UTF-8, EUC-JP or Shift_JIS; set the encoding"
+            ),
+            EncodingError::Unmappable {
+                encoding,
+                character,
+            } => write!(f, "{character} cannot be written in {encoding}"),
+        }
+    }
+}
+
+impl std::error::Error for EncodingError {}
+
+/// Reason why a word cannot be turned back into source
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DisassembleError {
+    /// A field holds a code that no statement assembles to
+    InvalidField {
+        address: MachineAddress,
+        code: MachineCode,
+        field: &'static str,
+    },
+    /// The branch target has no word to put a label on
+    MissingTarget {
+        address: MachineAddress,
+        code: MachineCode,
+        target: MachineAddress,
+    },
+    /// The source written for the word assembles to another word, or not at all
+    Unrepresentable {
+        address: MachineAddress,
+        code: MachineCode,
+    },
+}
+
+impl fmt::Display for DisassembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisassembleError::InvalidField {
+                address,
+                code,
+                field,
+            } => write!(f, "{address:03X}  {code:010X}: invalid {field} field"),
+            DisassembleError::MissingTarget {
+                address,
+                code,
+                target,
+            } => write!(
+                f,
+                "{address:03X}  {code:010X}: no word at the target {target:03X}"
+            ),
+            DisassembleError::Unrepresentable { address, code } => {
+                write!(
+                    f,
+                    "{address:03X}  {code:010X}: no source assembles to this word"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DisassembleError {}
+
+/// Reason why the simulator stops before the microprogram halts
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SimulateError {
+    /// Execution reached an address with no word
+    EmptyWord { address: MachineAddress },
+    /// A field holds a code that the machine does not execute
+    InvalidField {
+        address: MachineAddress,
+        code: MachineCode,
+        field: &'static str,
+    },
+    /// `RETURN` was executed with no return address saved
+    StackUnderflow { address: MachineAddress },
+    /// The microprogram did not halt within the given number of cycles
+    CycleLimit { cycles: u64 },
+}
+
+impl fmt::Display for SimulateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulateError::EmptyWord { address } => {
+                write!(f, "{address:03X}: no word at this address")
+            }
+            SimulateError::InvalidField {
+                address,
+                code,
+                field,
+            } => write!(f, "{address:03X}  {code:010X}: invalid {field} field"),
+            SimulateError::StackUnderflow { address } => {
+                write!(f, "{address:03X}: RETURN without a return address")
+            }
+            SimulateError::CycleLimit { cycles } => {
+                write!(f, "The microprogram did not halt within {cycles} cycles")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SimulateError {}
+
+#[cfg(test)]
+mod tests {
+    use super::chars;
+
+    #[test]
+    fn test_chars() {
+        let source = "; 足し算\n\tGOTO LOOP\n";
+        let start = source.find("LOOP").unwrap();
+        assert_eq!(start, 18);
+        assert_eq!(chars(source, &(start..start + 4)), 12..16);
+    }
+}
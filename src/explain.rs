@@ -0,0 +1,270 @@
+use crate::codegen;
+use crate::decode::*;
+use crate::error::{Diagnostics, Error};
+use crate::expression;
+use crate::parser::{MachineAddress, MachineCode};
+use crate::symbol;
+use crate::Options;
+
+use std::fmt;
+
+/// How the 16 LSBs of a word are used, named after the encodings in `codegen`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Layout {
+    /// A long literal fills the 16 LSBs
+    Llt,
+    /// TS and EX, with the 9 LSBs unused
+    TsAndEx,
+    /// TS and EX, with a short literal in the 9 LSBs
+    TsExAndLt,
+    /// TS and EX, with a branch address spread over the 16 LSBs
+    WithReference,
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, description) = match self {
+            Layout::Llt => ("Llt", "a long literal fills the 16 LSBs"),
+            Layout::TsAndEx => ("TsAndEx", "TS and EX, with the 9 LSBs unused"),
+            Layout::TsExAndLt => ("TsExAndLt", "TS and EX, with a short literal in the 9 LSBs"),
+            Layout::WithReference => ("WithReference", "TS and EX, with a branch address"),
+        };
+        write!(f, "{name}: {description}")
+    }
+}
+
+/// Bit layout of one word
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Explanation {
+    /// Address the word is taken to be placed at
+    pub address: MachineAddress,
+    pub code: MachineCode,
+    pub fields: Fields,
+    pub layout: Layout,
+}
+
+/// Field of a word as shown by [`Explanation`]
+struct Row {
+    name: &'static str,
+    /// Position of the MSB
+    high: u32,
+    width: u32,
+    meaning: String,
+}
+
+impl Explanation {
+    fn rows(&self) -> Vec<Row> {
+        let fields = &self.fields;
+        let row = |name, high, width, meaning: String| Row {
+            name,
+            high,
+            width,
+            meaning,
+        };
+        let symbolic = |mnemonic: &str| match mnemonic {
+            "?" => "? (no statement assembles to this code)".to_string(),
+            mnemonic => mnemonic.to_string(),
+        };
+        let [lb, rb, al, sh, sb, mm, sq, ts, ex] = fields.mnemonics().map(symbolic);
+
+        let mut rows = vec![
+            row("LB", 39, 4, lb),
+            row("RB", 35, 4, rb),
+            row("AL", 31, 3, al),
+            row("SH", 28, 3, sh),
+            row("SB", 25, 4, sb),
+            row("MM", 21, 2, mm),
+            row("SQ", 19, 4, sq),
+        ];
+        match self.layout {
+            Layout::Llt => rows.push(row(
+                "LLT",
+                15,
+                16,
+                format!("{:04X}", fields.literal.unwrap()),
+            )),
+            Layout::TsAndEx | Layout::TsExAndLt | Layout::WithReference => {
+                rows.push(match fields.ts {
+                    Some(_) => row("TS", 15, 3, ts),
+                    None => row("ADDR", 15, 3, "branch address".to_string()),
+                });
+                rows.push(row("EX", 12, 4, ex));
+                rows.push(match (fields.literal, fields.target) {
+                    (Some(literal), _) => row("SLT", 8, 9, format!("{literal:03X}")),
+                    (None, Some(target)) => row("ADDR", 8, 9, format!("target {target:03X}")),
+                    (None, None) => row("-", 8, 9, "unused".to_string()),
+                });
+            }
+        }
+        rows
+    }
+
+    /// How the branch address is spread over the 16 LSBs
+    fn address_bits(&self) -> Option<&'static str> {
+        if self.layout != Layout::WithReference {
+            return None;
+        }
+        Some(match self.fields.sq {
+            SQ_B | SQ_BP => "ADDR holds address bits 11-9, and the 9 LSBs bits 8-0",
+            SQ_IOP => "the 9 LSBs hold address bits 11-4",
+            SQ_IRA => "the LSB of ADDR holds address bit 11, and the 9 LSBs bits 10-4 and 1-0",
+            SQ_IAB => "the LSB of ADDR holds address bit 11, and the 9 LSBs bits 10-6 and 1-0",
+            _ => "the 9 LSBs hold address bits 8-0, and bits 11-9 are those of the word's address",
+        })
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:03X}  {:010X}", self.address, self.code)?;
+        writeln!(f, "16 LSBs  {}", self.layout)?;
+        if let Some(address_bits) = self.address_bits() {
+            writeln!(f, "         {address_bits}")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "FIELD  BITS   WIDTH  CODE              VALUE")?;
+        for row in self.rows() {
+            let low = row.high + 1 - row.width;
+            let bits = if row.width == 1 {
+                format!("{}", row.high)
+            } else {
+                format!("{}-{low}", row.high)
+            };
+            let code = format!(
+                "{:0width$b}",
+                (self.code >> low) & ((1 << row.width) - 1),
+                width = row.width as usize
+            );
+            writeln!(
+                f,
+                "{:<6} {bits:<6} {:>5}  {code:<16}  {}",
+                row.name, row.width, row.meaning
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Explains the word `code` placed at `address`
+///
+/// The layout of the 16 LSBs is told from the RB and SQ fields, as `codegen` chooses it.
+pub fn explain(address: MachineAddress, code: MachineCode) -> Explanation {
+    let fields = Fields::decode(address, code);
+    let layout = match (fields.rb, fields.target) {
+        (RB_LLT, _) => Layout::Llt,
+        (RB_SLT, _) => Layout::TsExAndLt,
+        (_, Some(_)) => Layout::WithReference,
+        (_, None) => Layout::TsAndEx,
+    };
+    Explanation {
+        address,
+        code,
+        fields,
+        layout,
+    }
+}
+
+/// Explains the word that the first instruction of `source` assembles to, with the constants
+/// of `options` defined
+///
+/// Labels that no instruction with an explicit address defines are taken to be at 000, so that
+/// an instruction branching anywhere can be explained on its own. The errors are those of the
+/// instruction alone, such as a literal sharing the 16 LSBs with a branch address.
+pub fn explain_source(source: &str, options: &Options) -> Result<Explanation, Diagnostics> {
+    let mut errors = Vec::<Error>::new();
+    let mut ast =
+        crate::read_program(source, &options.defines, &mut errors).map(|program| program.ast);
+    if let Some(ast) = &mut ast {
+        let labels = expression::label_addresses(&ast.instructions);
+        let first = ast.instructions.get_mut(..1).unwrap_or_default();
+        errors.extend(expression::evaluate_literals(first, |label| {
+            Some(labels.get(label).copied().unwrap_or(0x000))
+        }));
+    }
+    let instructions = match &ast {
+        Some(ast) if errors.is_empty() => &ast.instructions,
+        _ => return Err(Diagnostics::from_iter(errors)),
+    };
+    let instruction = match instructions.first() {
+        Some(instruction) => instruction,
+        // a program without instructions
+        None => {
+            return Err(Diagnostics::from(Error::UnexpectedToken {
+                span: source.len()..source.len(),
+            }))
+        }
+    };
+
+    let (mut symbol_table, _) = symbol::create_symbol_table(instructions);
+    if let Some((label, _)) = instruction
+        .test_and_sequence_statement
+        .as_ref()
+        .and_then(|(statement, _)| symbol::reference(statement))
+    {
+        symbol_table.entry(label).or_insert(0x000);
+    }
+
+    let address = instruction.address.unwrap_or(0x000);
+    match codegen::encode(instruction, &symbol_table) {
+        Ok(code) => Ok(explain(address, code)),
+        Err(reason) => Err(Diagnostics::from(Error::Encoding {
+            location: instruction.location(),
+            span: codegen::statement_span(instruction, &reason),
+            reason,
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{explain, explain_source, Layout};
+    use crate::error::{Conflict, EncodeError, Error};
+    use crate::{assemble, Options};
+
+    #[test]
+    fn test_explain() {
+        let explanation = explain(0x000, 0xAA1F0FFE01);
+        assert_eq!(explanation.layout, Layout::TsExAndLt);
+        let text = explanation.to_string();
+        assert!(text.starts_with("000  AA1F0FFE01\n16 LSBs  TsExAndLt:"));
+        assert!(text.contains("\nLB     39-36      4  1010              PC\n"));
+        assert!(text.contains("\nSLT    8-0        9  000000001         001\n"));
+
+        let explanation = explain(0x002, 0xFFFFF6BE05);
+        assert_eq!(explanation.layout, Layout::WithReference);
+        assert_eq!(explanation.fields.target, Some(0x811));
+        assert!(explanation
+            .to_string()
+            .contains("\nADDR   15-13      3  101 "));
+    }
+
+    #[test]
+    fn test_explain_source() {
+        let source = ".TITLE EXPLAIN\n* 000\n\tIRA MODES\n\tIR := PC\n.END\n";
+        let explanation = explain_source(source, &Options::default()).unwrap();
+        assert_eq!(explanation.layout, Layout::WithReference);
+        assert_eq!(explanation.fields.target, Some(0x000));
+        assert_eq!(explanation.fields.mnemonics()[8], "LIR");
+
+        let source = ".TITLE EXPLAIN\n* 000\n\tR0 := 1234\n\tC - 1\n.END\n";
+        let errors = explain_source(source, &Options::default()).unwrap_err();
+        assert!(matches!(
+            errors.errors(),
+            [Error::Encoding {
+                reason: EncodeError::LongLiteral(Conflict::Ex),
+                ..
+            }]
+        ));
+
+        // a constant given on the command line is read as its value, not as a label
+        let source = ".TITLE EXPLAIN\n* 000\n\tR0 := R0 AND MASK\n.END\n";
+        let options = Options {
+            defines: vec![("MASK".to_string(), 0x0F)],
+        };
+        let explanation = explain_source(source, &options).unwrap();
+        let plain = ".TITLE EXPLAIN\n* 000\n\tR0 := R0 AND 0F\n.END\n";
+        let object = assemble(plain, &Options::default()).unwrap();
+        assert_eq!(explanation.code, object.code[0].1);
+        assert_eq!(explanation.layout, Layout::TsExAndLt);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::error::{Error, ExpressionError};
+use crate::parser::{
+    CalculationStatement, ExStatement, Instruction, LbusOrRbus, Literal, MachineAddress, Rbus,
+    RbusSource, Spanned, Statement,
+};
+
+use logos::Span;
+
+use std::collections::{HashMap, HashSet};
+
+/// Number written as a calculation, such as `BASE+10` or `HIGH(TABLE)`
+///
+/// Names are read as constants, then as hexadecimal numbers, and are otherwise labels.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Expression {
+    Number(u16),
+    /// Constant with its value, kept by name so that its use can be recorded
+    Constant(String, u16),
+    Label(String),
+    Unary(UnaryOperator, Box<Spanned<Expression>>),
+    Binary(
+        BinaryOperator,
+        Box<Spanned<Expression>>,
+        Box<Spanned<Expression>>,
+    ),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnaryOperator {
+    /// `-`
+    Negate,
+    /// `~`, which complements the 16 bits of the Rbus
+    Not,
+    /// `HIGH()`, the upper byte of the 16 LSBs
+    High,
+    /// `LOW()`, the lower byte
+    Low,
+}
+
+/// Binary operators, binding tighter the earlier they come
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinaryOperator {
+    Multiply,
+    Divide,
+    Add,
+    Subtract,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+}
+
+/// Value of `expression`, with `label` giving the address of a label
+///
+/// The calculation is carried out on signed integers, so that only the result has to be in range.
+fn value(
+    (expression, span): &Spanned<Expression>,
+    label: &dyn Fn(&str) -> Result<i64, ExpressionError>,
+) -> Result<i64, Spanned<ExpressionError>> {
+    match expression {
+        Expression::Number(number) | Expression::Constant(_, number) => Ok(i64::from(*number)),
+        Expression::Label(name) => label(name).map_err(|reason| (reason, span.clone())),
+        Expression::Unary(operator, operand) => {
+            let operand = value(operand, label)?;
+            match operator {
+                UnaryOperator::Negate => operand.checked_neg(),
+                UnaryOperator::Not => Some(!operand & 0xFFFF),
+                UnaryOperator::High => Some((operand >> 8) & 0xFF),
+                UnaryOperator::Low => Some(operand & 0xFF),
+            }
+            .ok_or((ExpressionError::Overflow, span.clone()))
+        }
+        Expression::Binary(operator, left, right) => {
+            let (left, right_span) = (value(left, label)?, &right.1);
+            let right = value(right, label)?;
+            match operator {
+                BinaryOperator::Multiply => left.checked_mul(right),
+                BinaryOperator::Divide if right == 0 => {
+                    return Err((ExpressionError::DivisionByZero, right_span.clone()))
+                }
+                BinaryOperator::Divide => left.checked_div(right),
+                BinaryOperator::Add => left.checked_add(right),
+                BinaryOperator::Subtract => left.checked_sub(right),
+                BinaryOperator::ShiftLeft => u32::try_from(right)
+                    .ok()
+                    .and_then(|right| 1i64.checked_shl(right))
+                    .and_then(|factor| left.checked_mul(factor)),
+                BinaryOperator::ShiftRight => {
+                    u32::try_from(right).ok().map(|right| left >> right.min(63))
+                }
+                BinaryOperator::And => Some(left & right),
+                BinaryOperator::Or => Some(left | right),
+            }
+            .ok_or((ExpressionError::Overflow, span.clone()))
+        }
+    }
+}
+
+/// Value of `expression` as a 16-bit number
+fn evaluate(
+    expression: &Spanned<Expression>,
+    label: &dyn Fn(&str) -> Result<i64, ExpressionError>,
+) -> Result<u16, Error> {
+    let value =
+        value(expression, label).map_err(|(reason, span)| Error::Expression { span, reason })?;
+    u16::try_from(value).map_err(|_| Error::Expression {
+        span: expression.1.clone(),
+        reason: ExpressionError::OutOfRange {
+            value,
+            max: u16::MAX,
+        },
+    })
+}
+
+/// Calculates the value of a constant, from numbers and constants defined before it
+pub fn evaluate_constant(expression: &Spanned<Expression>) -> Result<u16, Error> {
+    evaluate(expression, &|name| {
+        Err(ExpressionError::LabelInConstant(name.to_string()))
+    })
+}
+
+/// Calculates the address written in the head of each instruction
+///
+/// Addresses are needed to place the instructions, before labels have addresses, so they are
+/// calculated from numbers and constants only. An address beyond the control store is left to
+/// placement to report.
+pub fn evaluate_addresses(instructions: &mut [Instruction]) -> Vec<Error> {
+    let mut errors = Vec::<Error>::new();
+    let label = |name: &str| Err(ExpressionError::LabelInAddress(name.to_string()));
+
+    for instruction in instructions {
+        if let Some(expression) = &instruction.address_expression {
+            match evaluate(expression, &label) {
+                Ok(address) => instruction.address = Some(address),
+                Err(error) => errors.push(error),
+            }
+        }
+    }
+
+    errors
+}
+
+/// Addresses of the labels of `instructions`, taking the first definition of each
+///
+/// A label left without an address, which placement reports, is taken to be at 000.
+pub fn label_addresses(instructions: &[Instruction]) -> HashMap<String, MachineAddress> {
+    let mut addresses = HashMap::<String, MachineAddress>::new();
+    for instruction in instructions {
+        if let Some((label, _)) = &instruction.label {
+            addresses
+                .entry(label.to_string())
+                .or_insert(instruction.address.unwrap_or(0x000));
+        }
+    }
+    addresses
+}
+
+/// Calculates the literals of `instructions`, with `labels` giving the address of each label
+pub fn evaluate_literals(
+    instructions: &mut [Instruction],
+    labels: impl Fn(&str) -> Option<MachineAddress>,
+) -> Vec<Error> {
+    let mut errors = Vec::<Error>::new();
+    let label = |name: &str| {
+        labels(name)
+            .map(i64::from)
+            .ok_or_else(|| ExpressionError::UnresolvedLabel(name.to_string()))
+    };
+
+    for literal in instructions.iter_mut().flat_map(literals) {
+        match evaluate(&literal.expression, &label) {
+            Ok(value) => literal.value = value,
+            Err(error) => errors.push(error),
+        }
+    }
+
+    errors
+}
+
+/// Names of the constants that the addresses and literals of `instructions` read
+pub fn used_constants(instructions: &mut [Instruction]) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for instruction in instructions {
+        if let Some(expression) = &instruction.address_expression {
+            constant_names(expression, &mut names);
+        }
+        for literal in literals(instruction) {
+            constant_names(&literal.expression, &mut names);
+        }
+    }
+    names
+}
+
+/// Adds the names of the constants that `expression` reads to `names`
+pub fn constant_names((expression, _): &Spanned<Expression>, names: &mut HashSet<String>) {
+    match expression {
+        Expression::Number(_) | Expression::Label(_) => (),
+        Expression::Constant(name, _) => {
+            names.insert(name.clone());
+        }
+        Expression::Unary(_, operand) => constant_names(operand, names),
+        Expression::Binary(_, left, right) => {
+            constant_names(left, names);
+            constant_names(right, names);
+        }
+    }
+}
+
+/// Literals that an instruction puts on the Rbus
+fn literals<'a, 'b>(instruction: &'b mut Instruction<'a>) -> Vec<&'b mut Literal> {
+    let mut literals = Vec::new();
+    if let Some((statement, _)) = &mut instruction.calculation_statement {
+        let statement = match statement {
+            CalculationStatement::Alu(_, statement) | CalculationStatement::Set(statement) => {
+                statement
+            }
+        };
+        match statement {
+            Statement::First(_, _, Rbus::Literal(literal)) => literals.push(literal),
+            Statement::AluThrough(alu_through) => {
+                if let LbusOrRbus::Rbus(Rbus::Literal(literal)) = &mut alu_through.lbus_or_rbus {
+                    literals.push(literal);
+                }
+            }
+            _ => (),
+        }
+    }
+    if let Some((ExStatement::C(RbusSource::Source(Rbus::Literal(literal))), _)) =
+        &mut instruction.ex_statement
+    {
+        literals.push(literal);
+    }
+    literals
+}
+
+/// Span running from the start of `first` to the end of `last`
+pub fn join(first: &Span, last: &Span) -> Span {
+    first.start..last.end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, BinaryOperator, Expression, UnaryOperator};
+    use crate::error::{Error, ExpressionError};
+
+    fn number(value: u16, span: std::ops::Range<usize>) -> Box<(Expression, logos::Span)> {
+        Box::new((Expression::Number(value), span))
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let label = |name: &str| match name {
+            "TABLE" => Ok(0x123),
+            _ => Err(ExpressionError::UnresolvedLabel(name.to_string())),
+        };
+
+        // HIGH(TABLE) << 4 | ~0FFF0
+        let high = (
+            Expression::Unary(
+                UnaryOperator::High,
+                Box::new((Expression::Label("TABLE".to_string()), 5..10)),
+            ),
+            0..11,
+        );
+        let shifted = (
+            Expression::Binary(BinaryOperator::ShiftLeft, Box::new(high), number(4, 15..16)),
+            0..16,
+        );
+        let not = (
+            Expression::Unary(UnaryOperator::Not, number(0xFFF0, 20..25)),
+            19..25,
+        );
+        let expression = (
+            Expression::Binary(BinaryOperator::Or, Box::new(shifted), Box::new(not)),
+            0..25,
+        );
+        assert_eq!(evaluate(&expression, &label), Ok(0x01F));
+
+        let expression = (
+            Expression::Binary(BinaryOperator::Divide, number(1, 0..1), number(0, 2..3)),
+            0..3,
+        );
+        assert_eq!(
+            evaluate(&expression, &label),
+            Err(Error::Expression {
+                span: 2..3,
+                reason: ExpressionError::DivisionByZero
+            })
+        );
+
+        let expression = (
+            Expression::Binary(BinaryOperator::Subtract, number(1, 0..1), number(2, 2..3)),
+            0..3,
+        );
+        assert_eq!(
+            evaluate(&expression, &label),
+            Err(Error::Expression {
+                span: 0..3,
+                reason: ExpressionError::OutOfRange {
+                    value: -1,
+                    max: 0xFFFF
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use crate::error::{Error, IncludeError};
+
+use logos::Span;
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// File read into a [`SourceMap`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+/// Run of the spliced text copied from one file
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Segment {
+    /// Start in the spliced text
+    start: usize,
+    len: usize,
+    /// Index of the file in `SourceMap::files`
+    file: usize,
+    /// Start in the text of the file
+    offset: usize,
+}
+
+/// Source program with the files named by its `.INCLUDE "file"` lines spliced in
+///
+/// The spliced text is what gets assembled, and its spans are traced back to the file and
+/// line they were written in. An included file holds instructions, without `.TITLE` and
+/// `.END`, and may include other files in turn.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SourceMap {
+    text: String,
+    /// The source program comes first, and the included files follow in the order read
+    files: Vec<SourceFile>,
+    segments: Vec<Segment>,
+    errors: Vec<Error>,
+}
+
+impl SourceMap {
+    /// Splices the files included by the source program `text`, read from `path`
+    ///
+    /// A file is looked up next to the file that includes it, and then in each of
+    /// `include_paths`. Files are read with `read`, so that the caller decides how they are
+    /// decoded. Errors found on the way, such as a file that includes itself, are kept in the
+    /// map with the span of the `.INCLUDE` line.
+    pub fn load(
+        path: &Path,
+        text: String,
+        include_paths: &[PathBuf],
+        mut read: impl FnMut(&Path) -> io::Result<String>,
+    ) -> Self {
+        let mut map = SourceMap::default();
+        map.files.push(SourceFile {
+            path: path.to_path_buf(),
+            text,
+        });
+        let mut stack = vec![path.canonicalize().unwrap_or_else(|_| path.to_path_buf())];
+        map.splice(0, &mut stack, include_paths, &mut read);
+        map
+    }
+
+    /// Spliced text of all files
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Errors found while splicing the included files
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    /// Paths of the included files, each once, in the order read
+    pub fn dependencies(&self) -> Vec<&Path> {
+        let mut paths = Vec::<&Path>::new();
+        for file in &self.files[1..] {
+            if !paths.contains(&file.path.as_path()) {
+                paths.push(&file.path);
+            }
+        }
+        paths
+    }
+
+    /// File that `span` of the spliced text was written in, and the span in that file
+    ///
+    /// A span running past the end of a file is cut at its end.
+    pub fn locate(&self, span: &Span) -> (&SourceFile, Span) {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.start <= span.start)
+            .saturating_sub(1);
+        match self.segments.get(index) {
+            Some(segment) => {
+                let start =
+                    segment.offset + span.start.min(segment.start + segment.len) - segment.start;
+                let end =
+                    segment.offset + span.end.min(segment.start + segment.len) - segment.start;
+                (&self.files[segment.file], start..end.max(start))
+            }
+            None => (&self.files[0], 0..0),
+        }
+    }
+
+    /// Appends `range` of the text of `file`
+    fn copy(&mut self, file: usize, range: Span) {
+        if range.is_empty() {
+            return;
+        }
+        self.segments.push(Segment {
+            start: self.text.len(),
+            len: range.len(),
+            file,
+            offset: range.start,
+        });
+        self.text += &self.files[file].text[range];
+    }
+
+    fn splice(
+        &mut self,
+        file: usize,
+        stack: &mut Vec<PathBuf>,
+        include_paths: &[PathBuf],
+        read: &mut impl FnMut(&Path) -> io::Result<String>,
+    ) {
+        let text = self.files[file].text.clone();
+        let mut copied = 0;
+        for (start, line) in lines(&text) {
+            let trimmed = line.trim_start();
+            match trimmed.strip_prefix(".INCLUDE") {
+                Some(rest) if rest.is_empty() || rest.starts_with([' ', '\t', '"', ';']) => (),
+                _ => continue,
+            }
+            // the directive runs up to a comment, which is kept
+            let directive = trimmed[..trimmed.find(';').unwrap_or(trimmed.len())].trim_end();
+            let directive_start = start + line.len() - trimmed.len();
+            let directive_end = directive_start + directive.len();
+
+            // the directive itself is kept in the spliced text when it fails, to be pointed at
+            self.copy(file, copied..directive_start);
+            copied = directive_end;
+            let span = self.text.len()..self.text.len() + directive.len();
+            let fail = |map: &mut SourceMap, path: String, reason| {
+                map.copy(file, directive_start..directive_end);
+                map.errors.push(Error::Include {
+                    span: span.clone(),
+                    path,
+                    reason,
+                });
+            };
+
+            let name = match name(&directive[".INCLUDE".len()..]) {
+                Some(name) => name,
+                None => {
+                    fail(self, String::new(), IncludeError::Malformed);
+                    continue;
+                }
+            };
+            let directory = self.files[file].path.parent().unwrap_or(Path::new(""));
+            let path = match std::iter::once(directory)
+                .chain(include_paths.iter().map(PathBuf::as_path))
+                .map(|directory| directory.join(name))
+                .find(|path| path.is_file())
+            {
+                Some(path) => path,
+                None => {
+                    fail(self, name.to_string(), IncludeError::NotFound);
+                    continue;
+                }
+            };
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+            if stack.contains(&canonical) {
+                fail(self, name.to_string(), IncludeError::Cycle);
+                continue;
+            }
+            let included_text = match read(&path) {
+                Ok(text) => text.trim_end_matches('\x1A').to_string(),
+                Err(why) => {
+                    fail(
+                        self,
+                        name.to_string(),
+                        IncludeError::Unreadable(why.to_string()),
+                    );
+                    continue;
+                }
+            };
+
+            self.files.push(SourceFile {
+                path,
+                text: included_text,
+            });
+            stack.push(canonical);
+            self.splice(self.files.len() - 1, stack, include_paths, read);
+            stack.pop();
+        }
+        self.copy(file, copied..text.len());
+    }
+}
+
+/// Lines of `text` with their start, without their line endings
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        if start >= text.len() {
+            return None;
+        }
+        let rest = &text[start..];
+        let len = rest.find(['\r', '\n']).unwrap_or(rest.len());
+        let line = (start, &rest[..len]);
+        start += len;
+        start += if rest[len..].starts_with("\r\n") {
+            2
+        } else {
+            rest[len..].len().min(1)
+        };
+        Some(line)
+    })
+}
+
+/// File name quoted after `.INCLUDE`
+fn name(rest: &str) -> Option<&str> {
+    let name = rest.trim().strip_prefix('"')?.strip_suffix('"')?;
+    (!name.is_empty() && !name.contains('"')).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SourceMap;
+    use crate::error::{Error, IncludeError};
+
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    /// Loads `main.s` from the `files` written to a directory named after the test
+    fn load(test: &str, files: &[(&str, &str)], include_paths: &[&str]) -> SourceMap {
+        let files = files
+            .iter()
+            .map(|(path, text)| (PathBuf::from(path), text.to_string()))
+            .collect::<HashMap<_, _>>();
+        let root = std::env::temp_dir().join(format!("rm1masm-{test}-{}", std::process::id()));
+        for (path, text) in &files {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        let include_paths = include_paths
+            .iter()
+            .map(|path| root.join(path))
+            .collect::<Vec<_>>();
+        let map = SourceMap::load(
+            &root.join("main.s"),
+            files[Path::new("main.s")].clone(),
+            &include_paths,
+            |path: &Path| -> io::Result<String> { std::fs::read_to_string(path) },
+        );
+        std::fs::remove_dir_all(root).unwrap();
+        map
+    }
+
+    #[test]
+    fn test_include() {
+        let map = load(
+            "include",
+            &[
+                ("main.s", ".TITLE T\n.INCLUDE \"a.s\" ; first\n.END\n"),
+                ("a.s", "* FETCH: 000\n\t.INCLUDE \"b.s\"\n"),
+                ("lib/b.s", "\tGOTO FETCH\r\n"),
+            ],
+            &["lib"],
+        );
+        assert_eq!(map.errors(), []);
+        assert_eq!(
+            map.text(),
+            ".TITLE T\n* FETCH: 000\n\t\tGOTO FETCH\r\n\n ; first\n.END\n"
+        );
+        let names = map
+            .dependencies()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a.s", "b.s"]);
+
+        let start = map.text().find("GOTO").unwrap();
+        let (file, span) = map.locate(&(start..start + 10));
+        assert!(file.path.ends_with("lib/b.s"));
+        assert_eq!(span, 1..11);
+        let start = map.text().find(".END").unwrap();
+        let (file, span) = map.locate(&(start..start + 4));
+        assert!(file.path.ends_with("main.s"));
+        assert_eq!(span, 32..36);
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let map = load(
+            "include_errors",
+            &[
+                (
+                    "main.s",
+                    ".TITLE T\n.INCLUDE \"a.s\"\n.INCLUDE \"c.s\"\n.INCLUDE a.s\n.END\n",
+                ),
+                ("a.s", ".INCLUDE \"main.s\"\n"),
+            ],
+            &[],
+        );
+        let errors = map
+            .errors()
+            .iter()
+            .map(|error| match error {
+                Error::Include { span, path, reason } => {
+                    (&map.text()[span.clone()], path.as_str(), reason.clone())
+                }
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                (".INCLUDE \"main.s\"", "main.s", IncludeError::Cycle),
+                (".INCLUDE \"c.s\"", "c.s", IncludeError::NotFound),
+                (".INCLUDE a.s", "", IncludeError::Malformed),
+            ]
+        );
+        let (file, span) = map.locate(&map.errors()[0].span());
+        assert!(file.path.ends_with("a.s"));
+        assert_eq!(span, 0..17);
+    }
+}
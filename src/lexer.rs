@@ -1,8 +1,14 @@
 use crate::token::Token;
 use logos::{Logos, Span};
 
-pub fn tokenize(input: &str) -> Vec<(Token, Span)> {
-    Token::lexer(input).spanned().collect()
+/// Splits `input` into tokens
+///
+/// Lines may end in LF, CRLF or CR, and a `^Z` (0x1A) at the end of `input`, as DOS editors
+/// leave, is ignored.
+pub fn tokenize(input: &str) -> Vec<(Token<'_>, Span)> {
+    Token::lexer(input.trim_end_matches('\x1A'))
+        .spanned()
+        .collect()
 }
 
 #[cfg(test)]
@@ -87,6 +93,15 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_unicode_string() {
+        // spans count bytes
+        let input = "加算 R0 ; 足し算";
+        let expected = vec![(Token::String("加算"), 0..6), (Token::String("R0"), 7..9)];
+        let actual = tokenize(input);
+        assert_eq!(expected, actual);
+    }
+
     #[test]
     fn test_hexadecimal() {
         let input = "01 23 45 67 89 0AB 1cd 2Ef 3FFFFF";
@@ -176,6 +191,24 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_expression_operators() {
+        let input = "/<<>> & |~(BASE)";
+        let expected = vec![
+            (Token::Slash, 0..1),
+            (Token::LessLess, 1..3),
+            (Token::GreaterGreater, 3..5),
+            (Token::Ampersand, 6..7),
+            (Token::Bar, 8..9),
+            (Token::Tilde, 9..10),
+            (Token::LeftParen, 10..11),
+            (Token::String("BASE"), 11..15),
+            (Token::RightParen, 15..16),
+        ];
+        let actual = tokenize(input);
+        assert_eq!(expected, actual);
+    }
+
     #[test]
     fn test_dollar() {
         let input = "$ $    $";
@@ -239,4 +272,70 @@ mod tests {
         let actual = tokenize(input);
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_line_endings() {
+        let input = "*\n*\r\n*\r*";
+        let expected = vec![
+            (Token::Star, 0..1),
+            (Token::Eol, 1..2),
+            (Token::Star, 2..3),
+            (Token::Eol, 3..5),
+            (Token::Star, 5..6),
+            (Token::Eol, 6..7),
+            (Token::Star, 7..8),
+        ];
+        let actual = tokenize(input);
+        assert_eq!(expected, actual);
+
+        // blank lines are kept apart whatever the line ending
+        let input = "\r\n\r\n\r\r\n\n";
+        let expected = vec![
+            (Token::Eol, 0..2),
+            (Token::Eol, 2..4),
+            (Token::Eol, 4..5),
+            (Token::Eol, 5..7),
+            (Token::Eol, 7..8),
+        ];
+        let actual = tokenize(input);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_comment() {
+        // at the end of a line
+        for (input, eol) in [("R0 ; A := B\n*", 11..12), ("R0 ; A := B\r\n*", 11..13)] {
+            let expected = vec![
+                (Token::String("R0"), 0..2),
+                (Token::Eol, eol.clone()),
+                (Token::Star, eol.end..eol.end + 1),
+            ];
+            let actual = tokenize(input);
+            assert_eq!(expected, actual);
+        }
+
+        // on its own line, and on the last line without a line ending
+        let input = "; R0 := R1\rR0\n;; *";
+        let expected = vec![
+            (Token::Eol, 10..11),
+            (Token::String("R0"), 11..13),
+            (Token::Eol, 13..14),
+        ];
+        let actual = tokenize(input);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_end_of_file() {
+        let input = ".END\r\n\x1A";
+        let expected = vec![(Token::DotString("END"), 0..4), (Token::Eol, 4..6)];
+        let actual = tokenize(input);
+        assert_eq!(expected, actual);
+
+        // only a trailing ^Z is ignored
+        let input = "\x1A.END";
+        let expected = vec![(Token::Error, 0..1), (Token::DotString("END"), 1..5)];
+        let actual = tokenize(input);
+        assert_eq!(expected, actual);
+    }
 }
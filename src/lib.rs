@@ -0,0 +1,731 @@
+//! MICRO-1 micro assembler
+//!
+//! ```
+//! let source = ".TITLE EXAMPLE\n* FETCH: 000\n\tGOTO FETCH\n.END\n";
+//! let object = rm1masm::assemble(source, &rm1masm::Options::default()).unwrap();
+//! assert_eq!(object.title, "EXAMPLE");
+//! assert_eq!(object.code, vec![(0x000, 0xFFFFF8FE00)]);
+//! ```
+
+mod codegen;
+mod constant;
+mod decode;
+mod diff;
+mod disasm;
+mod encoding;
+mod error;
+mod explain;
+mod expression;
+mod include;
+mod lexer;
+mod listing;
+mod map;
+mod object;
+mod parser;
+mod placement;
+mod sim;
+mod symbol;
+mod token;
+mod writer;
+
+pub use constant::parse_value;
+pub use decode::Fields;
+pub use diff::{diff, FieldChange, WordDifference};
+pub use disasm::disassemble;
+pub use encoding::Encoding;
+pub use error::{
+    Conflict, Diagnostics, DisassembleError, EncodeError, EncodingError, Error, ExpressionError,
+    LbusDestination, Location, ReadError, SimulateError, Warning,
+};
+pub use explain::{explain, explain_source, Explanation, Layout};
+pub use include::{SourceFile, SourceMap};
+pub use listing::write_listing;
+pub use map::{write_map, write_map_json};
+pub use object::{read_memory, read_object};
+pub use parser::{MachineAddress, MachineCode};
+pub use sim::{Registers, Simulator};
+pub use symbol::{ReferenceKind, Symbol};
+pub use writer::{
+    write_binary, write_cm, write_coe, write_intel_hex, write_json, write_memh, write_mif,
+    write_verilog, write_vhdl, Endian, ImageOptions, ObjectWriter, WriterRegistry, NOP,
+};
+
+use logos::Span;
+
+/// Options for [`assemble`]
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Constants defined before the program, as `-D NAME=VALUE` does
+    pub defines: Vec<(String, u16)>,
+}
+
+/// An assembled microprogram
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Object {
+    /// Title given by `.TITLE`
+    pub title: String,
+    /// Control store words in source order
+    pub code: Vec<(MachineAddress, MachineCode)>,
+    /// Span of the head of the instruction each word of `code` is assembled from
+    ///
+    /// Empty for an object read by [`read_object`], as are `symbols`.
+    pub spans: Vec<Span>,
+    /// Labels sorted by address
+    pub symbols: Vec<Symbol>,
+    /// Warnings found while assembling
+    pub diagnostics: Diagnostics,
+}
+
+/// Program read from the source, with the explicit addresses of its instructions calculated
+struct Program<'a> {
+    ast: parser::Ast<'a>,
+    constants: constant::Constants,
+    /// Whether every line was parsed, without skipping any in error recovery
+    parsed: bool,
+}
+
+/// Reads `source` up to where instructions are placed, adding the errors found to `errors`
+///
+/// This is the part of [`assemble`] that [`explain_source`] shares. `None` is returned when
+/// nothing could be parsed.
+fn read_program<'a>(
+    source: &'a str,
+    defines: &[(String, u16)],
+    errors: &mut Vec<Error>,
+) -> Option<Program<'a>> {
+    // lexical analysis
+    let tokens = lexer::tokenize(source);
+    errors.extend(
+        tokens
+            .iter()
+            .filter(|(token, _)| *token == token::Token::Error)
+            .map(|(_, span)| Error::InvalidToken { span: span.clone() }),
+    );
+
+    // constant definitions
+    let (tokens, constants, errs) = constant::collect(tokens, defines);
+    errors.extend(errs);
+
+    // syntactic analysis
+    let (ast, errs) = parser::parse(tokens, &constants);
+    let parsed = errs.is_empty();
+    errors.extend(
+        errs.into_iter()
+            .filter(|err| err.found() != Some(&token::Token::Error))
+            .map(|err| Error::UnexpectedToken { span: err.span() }),
+    );
+    let mut ast = ast?;
+
+    // explicit addresses
+    errors.extend(expression::evaluate_addresses(&mut ast.instructions));
+
+    Some(Program {
+        ast,
+        constants,
+        parsed,
+    })
+}
+
+/// Assembles a MICRO-1 microprogram
+///
+/// Every phase runs even if an earlier one failed, so that all errors are reported at once.
+///
+/// `source` is read as it is, and an `.INCLUDE` line in it is a syntax error: a program that
+/// includes files is spliced with [`SourceMap::load`] first, and its spliced text is assembled.
+/// The spans in the object and the diagnostics then point into that text, and
+/// [`Diagnostics::eprint_map`] reports them in the files they were written in.
+///
+/// ```
+/// let directory = std::env::temp_dir();
+/// std::fs::write(directory.join("fetch.inc"), "\tGOTO FETCH\n").unwrap();
+///
+/// let path = directory.join("example.mal");
+/// let source = ".TITLE EXAMPLE\n* FETCH: 000\n.INCLUDE \"fetch.inc\"\n.END\n";
+/// let source_map = rm1masm::SourceMap::load(&path, source.to_string(), &[], |path| {
+///     std::fs::read_to_string(path)
+/// });
+/// assert!(source_map.errors().is_empty());
+///
+/// let object = rm1masm::assemble(source_map.text(), &rm1masm::Options::default()).unwrap();
+/// assert_eq!(object.code, vec![(0x000, 0xFFFFF8FE00)]);
+/// ```
+pub fn assemble(source: &str, options: &Options) -> Result<Object, Diagnostics> {
+    let mut errors = Vec::<Error>::new();
+    let Program {
+        ast,
+        constants,
+        parsed,
+    } = match read_program(source, &options.defines, &mut errors) {
+        Some(program) => program,
+        None => return Err(Diagnostics::from_iter(errors)),
+    };
+
+    // address assignment
+    let references = symbol::ReferenceIndex::new(&ast.instructions);
+    let (mut ast, errs) = ast.set_address(&references);
+    errors.extend(errs);
+
+    // literals, which may hold label addresses
+    let labels = expression::label_addresses(&ast.instructions);
+    errors.extend(expression::evaluate_literals(
+        &mut ast.instructions,
+        |label| labels.get(label).copied(),
+    ));
+
+    // constants, whose uses are read from the parsed expressions
+    if parsed {
+        // uses in instructions skipped by error recovery are lost, as labels are below
+        let used = expression::used_constants(&mut ast.instructions);
+        errors.extend(constants.check_unused(&used));
+    }
+
+    // symbol resolution
+    let (symbol_table, errs) = symbol::create_symbol_table(&ast.instructions);
+    errors.extend(errs);
+    let warnings = symbol::check_label_names(&ast.instructions);
+    errors.extend(placement::verify(&ast.instructions, &symbol_table));
+    if parsed {
+        // labels of instructions skipped by error recovery would be reported as unresolved
+        errors.extend(symbol::check_unresolved_symbols(
+            &symbol_table,
+            &references,
+            &ast.instructions,
+        ));
+    }
+
+    // code generation
+    match codegen::generate(&ast.instructions, &symbol_table) {
+        Ok(code) if errors.is_empty() => Ok(Object {
+            title: ast.title.to_string(),
+            code,
+            spans: ast
+                .instructions
+                .iter()
+                .map(|instruction| instruction.span.clone())
+                .collect(),
+            symbols: symbol::symbols(&symbol_table, &references),
+            diagnostics: Diagnostics::new(Vec::new(), warnings),
+        }),
+        Ok(_) => Err(Diagnostics::new(errors, warnings)),
+        Err(errs) => {
+            errors.extend(errs);
+            Err(Diagnostics::new(errors, warnings))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        assemble, write_listing, write_map, write_map_json, Conflict, EncodeError, Error,
+        ExpressionError, LbusDestination, Location, Options, Warning,
+    };
+
+    const SAMPLE: &str = "\
+.TITLE SAMPLE
+* FETCH: 000
+\tREAD
+\tPC := PC + 1
+* 001
+\tIR := MM
+* 002
+\tIOP OPS
+* OPS: 010
+\tGOTO FETCH
+* ADDI:
+\tGOTO FETCH
+\tR1 := R1 + 0FF
+* LOOP:
+\tIF ZER = 1 THEN DONE
+\tR2 := R2 - R3
+\tC - 1
+* DONE:
+\tCALL SUB
+*
+\tR3 := ZERO OR 1234
+*
+\tGOTO FETCH
+* SUB:
+\tRETURN
+\tIO := R0
+.END
+";
+
+    #[test]
+    fn test_assemble() {
+        let object = assemble(SAMPLE, &Options::default()).unwrap();
+        assert_eq!(object.title, "SAMPLE");
+        assert_eq!(
+            object.code,
+            vec![
+                (0x000, 0xAA1F0FFE01),
+                (0x001, 0xCFFFFFE800),
+                (0x002, 0xFFFFF5FE01),
+                (0x010, 0xFFFFF8FE00),
+                (0x011, 0x1A1C78FEFF),
+                (0x012, 0x233CB30013),
+                (0x013, 0xFFFFF11E16),
+                (0x014, 0xFB7CFF1234),
+                (0x015, 0xFFFFF8FE00),
+                (0x016, 0x0FFFF2EA00),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unresolved_symbol() {
+        let source = ".TITLE SAMPLE\n* 000\n\tGOTO NOWHERE\n.END\n";
+        let diagnostics = assemble(source, &Options::default()).unwrap_err();
+        assert_eq!(
+            diagnostics.errors(),
+            &[Error::UnresolvedSymbol {
+                location: Location {
+                    label: None,
+                    span: 14..19,
+                    address: Some(0x000),
+                },
+                span: 21..33,
+                symbol: "NOWHERE".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_encoding_error() {
+        let source = ".TITLE SAMPLE\n* LOOP: 000\n\tGOTO LOOP\n\tR0 := R0 + 1234\n.END\n";
+        let diagnostics = assemble(source, &Options::default()).unwrap_err();
+        assert_eq!(
+            diagnostics.errors(),
+            &[Error::Encoding {
+                location: Location {
+                    label: Some("LOOP".to_string()),
+                    span: 14..25,
+                    address: Some(0x000),
+                },
+                span: 38..53,
+                reason: EncodeError::LongLiteral(Conflict::Reference),
+            }]
+        );
+
+        let source = ".TITLE SAMPLE\n* 000\n\tR0 := R1\n\tIR := MM\n.END\n";
+        let diagnostics = assemble(source, &Options::default()).unwrap_err();
+        assert!(matches!(
+            diagnostics.errors(),
+            [Error::Encoding {
+                reason: EncodeError::LbusSource(LbusDestination::Ir),
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_misaligned_address() {
+        let source = ".TITLE SAMPLE\n* 000\n\tIOP OPS\n* OPS: 011\n\tGOTO FETCH\n.END\n";
+        let diagnostics = assemble(source, &Options::default()).unwrap_err();
+        assert!(matches!(
+            diagnostics.errors(),
+            [Error::MisalignedAddress { mask: 0xF, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_every_error_is_reported() {
+        let source = "\
+.TITLE SAMPLE
+* 000
+\tIOP OPS
+* OPS: 011
+\tGOTO NOWHERE
+* 020
+\tR0 := R0 + 1234
+\tC - 1
+.END
+";
+        let diagnostics = assemble(source, &Options::default()).unwrap_err();
+        assert!(matches!(
+            diagnostics.errors(),
+            [
+                Error::MisalignedAddress { .. },
+                Error::UnresolvedSymbol { .. },
+                Error::Encoding { .. },
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_invalid_token() {
+        let source = ".TITLE SAMPLE\n* 000\n\tR0 := R0 + 3FFFFF\n* 001\n\tR0 := R0 ^ R1\n.END\n";
+        let diagnostics = assemble(source, &Options::default()).unwrap_err();
+        let errors = diagnostics.errors();
+        assert!(errors.contains(&Error::InvalidToken { span: 32..38 }));
+        assert!(errors.contains(&Error::InvalidToken { span: 55..56 }));
+    }
+
+    #[test]
+    fn test_duplicate_label() {
+        let source = ".TITLE SAMPLE\n* LOOP: 000\n\tGOTO LOOP\n* LOOP: 001\n\tGOTO LOOP\n.END\n";
+        let diagnostics = assemble(source, &Options::default()).unwrap_err();
+        assert_eq!(
+            diagnostics.errors(),
+            &[Error::DuplicateLabel {
+                label: "LOOP".to_string(),
+                span: 39..43,
+                previous: 16..20,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_reserved_label() {
+        let source = "\
+.TITLE SAMPLE
+* FETCH: 001
+\tGOTO R0
+* R0:
+\tGOTO ADD
+* ADD:
+\tGOTO GOTO
+* GOTO:
+\tGOTO FSR
+* FSR:
+\tGOTO FETCH
+.END
+";
+        let object = assemble(source, &Options::default()).unwrap();
+        assert!(matches!(
+            object.diagnostics.warnings(),
+            [
+                Warning::FetchLabel { address: 0x001, .. },
+                Warning::RegisterLabel { .. },
+                Warning::HexadecimalLabel { .. },
+                Warning::KeywordLabel { .. },
+                Warning::RegisterLabel { label, .. },
+            ] if label == "FSR"
+        ));
+        assert!(assemble(SAMPLE, &Options::default())
+            .unwrap()
+            .diagnostics
+            .is_empty());
+    }
+
+    #[test]
+    fn test_constants() {
+        let source = "\
+.TITLE SAMPLE
+.EQU START 010
+.DEFINE MASK 0FF
+* START
+\tR0 := R0 AND MASK
+* PORT
+\tC := MASK
+.END
+";
+        let options = Options {
+            defines: vec![("PORT".to_string(), 0x020)],
+        };
+        let object = assemble(source, &options).unwrap();
+        assert_eq!(object.code, [(0x010, 0x0A5C3FFEFF), (0x020, 0xFAFFFFECFF)]);
+        assert!(object.diagnostics.is_empty());
+
+        let source = ".TITLE SAMPLE\n.EQU PORT 1\n.EQU UNUSED 2\n* PORT\n\tR0 := R1\n.END\n";
+        let diagnostics = assemble(source, &options).unwrap_err();
+        assert_eq!(
+            diagnostics.errors(),
+            [
+                Error::DuplicateConstant {
+                    name: "PORT".to_string(),
+                    span: 19..23,
+                    previous: None
+                },
+                Error::UnusedConstant {
+                    name: "UNUSED".to_string(),
+                    span: Some(31..37)
+                }
+            ]
+        );
+        assert!(diagnostics.warnings().is_empty());
+
+        // constants given on the command line have to be used too
+        let source = ".TITLE SAMPLE
+*
+	R0 := R1
+.END
+";
+        let diagnostics = assemble(source, &options).unwrap_err();
+        assert_eq!(
+            diagnostics.errors(),
+            [Error::UnusedConstant {
+                name: "PORT".to_string(),
+                span: None
+            }]
+        );
+    }
+
+    #[test]
+    fn test_expressions() {
+        let source = "\
+.TITLE SAMPLE
+.EQU BASE 010
+.EQU MASK BASE+0E0
+* ENTRY BASE+10
+\tR0 := ZERO OR MASK & 0FF
+*
+\tR1 := R1 + (BASE << 2) / D\"4 - 1
+*
+\tR2 := R2 AND ~MASK
+*
+\tC := HIGH(TABLE) + LOW(TABLE)
+* FF-1
+\tGOTO FETCH
+* FF+1
+\tGOTO FETCH
+* TABLE: 2*BASE+B\"11*100
+\tGOTO FETCH
+.END
+";
+        let plain = "\
+.TITLE SAMPLE
+* ENTRY: 020
+\tR0 := ZERO OR 0F0
+*
+\tR1 := R1 + 0F
+*
+\tR2 := R2 AND 0FF0F
+*
+\tC := 23
+* 0FE
+\tGOTO FETCH
+* 100
+\tGOTO FETCH
+* TABLE: 320
+\tGOTO FETCH
+.END
+";
+        let object = assemble(source, &Options::default()).unwrap();
+        assert_eq!(
+            object.code,
+            assemble(plain, &Options::default()).unwrap().code
+        );
+        assert!(object.diagnostics.is_empty());
+        // FF is read as a number whichever operator follows it
+        assert!(object.symbols.iter().all(|symbol| symbol.name != "FF"));
+
+        let source = "\
+.TITLE SAMPLE
+* LOOP 1/0
+\tR0 := R0 + 1 - 2
+* START
+\tR1 := R1 + UNDEFINED
+.END
+";
+        let span = |text: &str| {
+            let start = source.find(text).unwrap();
+            start..start + text.len()
+        };
+        let diagnostics = assemble(source, &Options::default()).unwrap_err();
+        assert_eq!(
+            diagnostics.errors(),
+            [
+                Error::Expression {
+                    span: span("/0").end - 1..span("/0").end,
+                    reason: ExpressionError::DivisionByZero
+                },
+                Error::Expression {
+                    span: span("1 - 2"),
+                    reason: ExpressionError::OutOfRange {
+                        value: -1,
+                        max: 0xFFFF
+                    }
+                },
+                Error::Expression {
+                    span: span("START"),
+                    reason: ExpressionError::LabelInAddress("START".to_string())
+                },
+                Error::Expression {
+                    span: span("UNDEFINED"),
+                    reason: ExpressionError::UnresolvedLabel("UNDEFINED".to_string())
+                },
+            ]
+        );
+
+        // a constant after `*` starts an address, rather than being a label without a colon
+        let source = ".TITLE SAMPLE\n.EQU BASE 011\n* BASE-1\n\tR0 := R1\n.END\n";
+        let plain = ".TITLE SAMPLE\n* 010\n\tR0 := R1\n.END\n";
+        let object = assemble(source, &Options::default()).unwrap();
+        assert_eq!(
+            object.code,
+            assemble(plain, &Options::default()).unwrap().code
+        );
+        assert!(object.symbols.is_empty());
+
+        let source = ".TITLE SAMPLE\n.EQU BASE 011\n* BASE ~1\n\tR0 := R1\n.END\n";
+        let diagnostics = assemble(source, &Options::default()).unwrap_err();
+        assert_eq!(
+            diagnostics.errors(),
+            [Error::UnexpectedToken { span: 28..37 }]
+        );
+    }
+
+    #[test]
+    fn test_address_collision() {
+        let source =
+            ".TITLE SAMPLE\n* 040\n\tGOTO FETCH\n* 041\n\tGOTO FETCH\n* 040\n\tGOTO FETCH\n.END\n";
+        let diagnostics = assemble(source, &Options::default()).unwrap_err();
+        assert_eq!(
+            diagnostics.errors(),
+            &[Error::AddressCollision {
+                location: Location {
+                    label: None,
+                    span: 50..55,
+                    address: Some(0x040),
+                },
+                previous: Location {
+                    label: None,
+                    span: 14..19,
+                    address: Some(0x040),
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_address_out_of_range() {
+        let source = ".TITLE SAMPLE\n* FFF\n\tR0 := R1\n*\n\tGOTO FETCH\n.END\n";
+        let diagnostics = assemble(source, &Options::default()).unwrap_err();
+        assert!(matches!(
+            diagnostics.errors(),
+            [Error::AddressOutOfRange {
+                location: Location {
+                    address: Some(0x1000),
+                    ..
+                }
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_unreachable_target() {
+        let source = ".TITLE SAMPLE\n* 1FF\n\tIF ZER = 1 THEN NEXT\n* NEXT:\n\tGOTO FETCH\n.END\n";
+        let diagnostics = assemble(source, &Options::default()).unwrap_err();
+        assert!(matches!(
+            diagnostics.errors(),
+            [Error::UnreachableTarget { target: 0x200, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_dispatch_placement() {
+        let source = "\
+.TITLE SAMPLE
+* FETCH: 000
+\tIOP OPS
+* OPS:
+\tGOTO FETCH
+* ADD:
+\tGOTO FETCH
+* 010
+\tGOTO FETCH
+* SUB:
+\tGOTO FETCH
+.END
+";
+        let object = assemble(source, &Options::default()).unwrap();
+        let addresses = object
+            .code
+            .iter()
+            .map(|(address, _)| *address)
+            .collect::<Vec<_>>();
+        assert_eq!(addresses, vec![0x000, 0x020, 0x021, 0x010, 0x011]);
+    }
+
+    #[test]
+    fn test_explicit_address_after_dispatch_target() {
+        let addresses = |source: &str| {
+            let object = assemble(source, &Options::default()).unwrap();
+            object
+                .code
+                .iter()
+                .map(|(address, _)| *address)
+                .collect::<Vec<_>>()
+        };
+
+        // the explicit address does not pull the jump table onto FETCH
+        let source = "\
+.TITLE SAMPLE
+* FETCH: 000
+\tIOP OPS
+* OPS:
+\tGOTO FETCH
+* 001
+\tGOTO FETCH
+*
+\tGOTO FETCH
+.END
+";
+        assert_eq!(addresses(source), [0x000, 0x010, 0x001, 0x002]);
+
+        // an instruction falling into an explicit address stays at the location counter
+        let source =
+            ".TITLE SAMPLE\n* 000\n\tGOTO FETCH\n*\n\tR0 := R1\n* 010\n\tGOTO FETCH\n.END\n";
+        assert_eq!(addresses(source), [0x000, 0x001, 0x010]);
+
+        // a dispatch target skips ahead as the location counter did
+        let source = ".TITLE SAMPLE\n* 000\n\tIRA MODES\n* MODES:\n\tGOTO FETCH\n.END\n";
+        assert_eq!(addresses(source), [0x000, 0x011]);
+
+        // a jump table past the end of the control store wraps around to 000
+        let source = format!(
+            ".TITLE SAMPLE\n* FE0\n{}\tIOP OPS\n*\n\tR0 := R1\n* OPS:\n\tGOTO FETCH\n.END\n",
+            "\tR0 := R1\n*\n".repeat(15)
+        );
+        let expected = (0xFE0..=0xFEF).chain([0x00F, 0x010]).collect::<Vec<_>>();
+        assert_eq!(addresses(&source), expected);
+    }
+
+    #[test]
+    fn test_unplaceable_block() {
+        let source = format!(".TITLE SAMPLE\n{}.END\n", "*\n".repeat(0x1001));
+        let diagnostics = assemble(&source, &Options::default()).unwrap_err();
+        assert!(matches!(
+            diagnostics.errors(),
+            [Error::Unplaceable { words: 0x1001, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_listing() {
+        let object = assemble(SAMPLE, &Options::default()).unwrap();
+        let mut listing = Vec::new();
+        write_listing(&mut listing, SAMPLE, &object).unwrap();
+        let listing = String::from_utf8(listing).unwrap();
+        let lines = listing.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[4],
+            "000  AA1F0FFE01 PC   SLT  ADD  NSH  PCS  RM   NSQ  NTS  NEX  0001  * FETCH: 000"
+        );
+        assert_eq!(lines[5].trim(), "READ");
+        assert_eq!(lines.last(), Some(&"SUB              016"));
+    }
+
+    #[test]
+    fn test_map() {
+        let object = assemble(SAMPLE, &Options::default()).unwrap();
+        let mut map = Vec::new();
+        write_map(&mut map, &object).unwrap();
+        let map = String::from_utf8(map).unwrap();
+        let lines = map.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[3..6],
+            ["000  FETCH", "010  OPS              IOP", "011  ADDI"]
+        );
+        assert_eq!(lines[11], "011  ADDI");
+
+        let mut json = Vec::new();
+        write_map_json(&mut json, &object).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["by_address"][1]["name"], "OPS");
+        assert_eq!(json["by_address"][1]["address"], 0x010);
+        assert_eq!(json["by_address"][1]["references"][0], "IOP");
+        assert_eq!(json["by_name"][0]["name"], "ADDI");
+    }
+}
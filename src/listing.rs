@@ -0,0 +1,42 @@
+use crate::decode::Fields;
+use crate::Object;
+
+use std::io::{self, Write};
+
+const HEADER: &str = "ADDR WORD       LB   RB   AL   SH   SB   MM   SQ   TS   EX   LIT   SOURCE";
+
+/// Writes a listing of `object`, which was assembled from `source`
+///
+/// Each source line is shown next to the address and the word of the instruction it starts,
+/// with the word split into its fields. The symbol table follows.
+pub fn write_listing(out: &mut dyn Write, source: &str, object: &Object) -> io::Result<()> {
+    writeln!(out, "{}", object.title)?;
+    writeln!(out)?;
+    writeln!(out, "{HEADER}")?;
+
+    let mut words = object.code.iter().zip(&object.spans).peekable();
+    let mut start = 0;
+    for line in source.split_inclusive('\n') {
+        let end = start + line.len();
+        let text = line.trim_end_matches(['\r', '\n']);
+
+        match words.next_if(|(_, span)| span.start < end) {
+            Some(((address, code), _)) => writeln!(
+                out,
+                "{address:03X}  {code:010X} {}  {text}",
+                Fields::decode(*address, *code)
+            )?,
+            None => writeln!(out, "{:<67}{text}", "")?,
+        }
+        start = end;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "SYMBOL           ADDR")?;
+    let mut symbols = object.symbols.iter().collect::<Vec<_>>();
+    symbols.sort_by(|a, b| a.name.cmp(&b.name));
+    for symbol in symbols {
+        writeln!(out, "{:<16} {:03X}", symbol.name, symbol.address)?;
+    }
+    Ok(())
+}
@@ -1,23 +1,18 @@
-mod codegen;
-mod lexer;
-mod parser;
-mod symbol;
-mod token;
-
-use ariadne::{Color, Fmt, Label, Report, ReportKind, Source};
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
-
-use parser::MachineCode;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 fn main() {
+    let writers = rm1masm::WriterRegistry::default();
+    let formats = writers.names().collect::<Vec<_>>();
+
     // Create the argument parser
     let matches = App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .about("MICRO-1 micro assembler written in Rust")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("input").help("source code").required(true))
         .arg(
             Arg::with_name("output")
@@ -26,84 +21,591 @@ fn main() {
                 .long("output")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("format")
+                .help("Sets the format of the output file")
+                .short("f")
+                .long("format")
+                .visible_alias("emit")
+                .value_name("FORMAT")
+                .possible_values(&formats)
+                .default_value("cm"),
+        )
+        .arg(
+            Arg::with_name("endian")
+                .help("Sets the byte order of the words in ihex and bin output")
+                .long("endian")
+                .value_name("ORDER")
+                .possible_values(&["big", "little"])
+                .default_value("big"),
+        )
+        .arg(
+            Arg::with_name("fill")
+                .help("Sets the word in hexadecimal, or nop, written to empty addresses")
+                .long("fill")
+                .value_name("WORD")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("depth")
+                .help("Sets the number of words in mif and coe output")
+                .long("depth")
+                .value_name("N")
+                .default_value("4096"),
+        )
+        .arg(
+            Arg::with_name("encoding")
+                .help("Sets the encoding of source programs and object files [default: detected]")
+                .long("encoding")
+                .value_name("ENCODING")
+                .possible_values(&rm1masm::Encoding::NAMES)
+                .case_insensitive(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("include")
+                .help("Adds a directory to look up .INCLUDE files in")
+                .short("I")
+                .value_name("DIR")
+                .multiple(true)
+                .number_of_values(1)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("define")
+                .help("Defines a constant, with the value written as an expression in source code")
+                .short("D")
+                .value_name("NAME=VALUE")
+                .multiple(true)
+                .number_of_values(1)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("deps")
+                .help("Writes Makefile rules for the files the output depends on")
+                .short("M")
+                .long("deps")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listing")
+                .help("Writes a listing file")
+                .short("l")
+                .long("listing")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("map")
+                .help("Writes a symbol map file")
+                .short("m")
+                .long("map")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("map-format")
+                .help("Sets the format of the symbol map file")
+                .long("map-format")
+                .value_name("FORMAT")
+                .possible_values(&["text", "json"])
+                .default_value("text"),
+        )
+        .subcommand(
+            SubCommand::with_name("disasm")
+                .about("Disassembles an object file into source code")
+                .arg(Arg::with_name("input").help("object file").required(true))
+                .arg(
+                    Arg::with_name("output")
+                        .help("Sets output path [default: standard output]")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compares two object files word by word")
+                .arg(Arg::with_name("first").help("object file").required(true))
+                .arg(Arg::with_name("second").help("object file").required(true))
+                .arg(
+                    Arg::with_name("first-source")
+                        .help("Takes labels for the first object from its source code")
+                        .long("first-source")
+                        .value_name("FILE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("second-source")
+                        .help("Takes labels for the second object from its source code")
+                        .long("second-source")
+                        .value_name("FILE")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("explain")
+                .about("Shows the bit layout of one microinstruction")
+                .arg(
+                    Arg::with_name("instruction")
+                        .help("statements of the instruction, or a 10-digit word in hexadecimal")
+                        .required(true)
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::with_name("address")
+                        .help("Sets the address the instruction is placed at")
+                        .long("address")
+                        .value_name("ADDRESS")
+                        .default_value("000"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sim")
+                .about("Runs an object file on a simulated MICRO-1")
+                .arg(Arg::with_name("input").help("object file").required(true))
+                .arg(
+                    Arg::with_name("memory")
+                        .help("Loads a main memory image")
+                        .short("M")
+                        .long("memory")
+                        .value_name("FILE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cycles")
+                        .help("Sets the number of cycles to give up after")
+                        .long("cycles")
+                        .value_name("N")
+                        .default_value("1000000"),
+                )
+                .arg(
+                    Arg::with_name("trace")
+                        .help("Prints the registers after every cycle")
+                        .long("trace"),
+                ),
+        )
         .get_matches();
 
+    match matches.subcommand() {
+        ("disasm", Some(matches)) => {
+            disasm(matches);
+            return;
+        }
+        ("diff", Some(matches)) => {
+            diff(matches);
+            return;
+        }
+        ("explain", Some(matches)) => {
+            explain(matches);
+            return;
+        }
+        ("sim", Some(matches)) => {
+            sim(matches);
+            return;
+        }
+        _ => (),
+    }
+
     // Read source program
     let input_path = matches.value_of("input").unwrap();
-    let mut input_file =
-        File::open(input_path).unwrap_or_else(|_| panic!("{}: No such file", input_path));
-    let mut source_program = String::new();
-    input_file
-        .read_to_string(&mut source_program)
-        .unwrap_or_else(|_| panic!("{}: No such file", input_path));
-
-    // lexical analysis
-    let tokens = lexer::tokenize(&source_program);
-
-    // syntactic analysis
-    let (ast, errs) = parser::parse(tokens);
-
-    if errs.is_empty() {
-        let ast = ast.unwrap();
-        if let Ok(ast) = ast.set_address() {
-            // symbol resolution
-            let symbol_table = symbol::create_symbol_table(&ast.instructions);
-            let unresolved_symbols =
-                symbol::check_unresolved_symbols(&symbol_table, &ast.instructions);
-            if !unresolved_symbols.is_empty() {
-                eprintln!("Unresolved symbols found");
-                for unresolved_symbol in unresolved_symbols {
-                    eprintln!("- {unresolved_symbol}");
+    let (source_map, encoding) = read_source(input_path, &matches, 1);
+    let source_program = source_map.text();
+
+    // Assemble the source program
+    let object = match rm1masm::assemble(source_program, &options(&matches)) {
+        Ok(object) => {
+            object.diagnostics.eprint_map(&source_map).unwrap();
+            object
+        }
+        Err(diagnostics) => {
+            diagnostics.eprint_map(&source_map).unwrap();
+            std::process::exit(1);
+        }
+    };
+
+    let writer = writers.get(matches.value_of("format").unwrap()).unwrap();
+    let options = rm1masm::ImageOptions {
+        endian: match matches.value_of("endian") {
+            Some("little") => rm1masm::Endian::Little,
+            _ => rm1masm::Endian::Big,
+        },
+        fill: match matches.value_of("fill").unwrap() {
+            "nop" => rm1masm::NOP,
+            fill => match rm1masm::MachineCode::from_str_radix(fill, 16) {
+                Ok(fill) if fill < 1 << 40 => fill,
+                _ => {
+                    eprintln!("--fill: not a 40-bit word in hexadecimal");
+                    std::process::exit(1);
                 }
+            },
+        },
+        depth: match matches.value_of("depth").unwrap().parse::<usize>() {
+            Ok(depth) => depth,
+            Err(_) => {
+                eprintln!("--depth: not a number of words");
                 std::process::exit(1);
             }
+        },
+    };
 
-            let code = codegen::generate(&ast.instructions);
-            if code.is_err() {
-                eprintln!("Code generation failed");
-                std::process::exit(1);
+    // Set a binary file name
+    let output_path = if let Some(output_file_name) = matches.value_of("output") {
+        PathBuf::from(output_file_name)
+    } else {
+        let mut output_path = PathBuf::from(input_path);
+        output_path.set_extension(writer.extension());
+        output_path
+    };
+
+    // Write a binary file, with the title of a CM object in the encoding of the source program
+    let mut binary = Vec::new();
+    writer
+        .write(&mut binary, &object, &options)
+        .unwrap_or_else(|why| {
+            eprintln!("{}: {why}", output_path.display());
+            std::process::exit(1);
+        });
+    if writer.name() == "cm" {
+        binary = encode(&output_path.display().to_string(), &binary, encoding);
+    }
+    let mut file = match File::create(&output_path) {
+        Err(why) => panic!("{}: {why}", output_path.display()),
+        Ok(file) => file,
+    };
+    file.write_all(&binary).unwrap();
+
+    // Write a listing file
+    if let Some(listing_path) = matches.value_of("listing") {
+        let mut file = match File::create(listing_path) {
+            Err(why) => panic!("{listing_path}: {why}"),
+            Ok(file) => file,
+        };
+        let mut listing = Vec::new();
+        rm1masm::write_listing(&mut listing, source_program, &object).unwrap();
+        file.write_all(&encode(listing_path, &listing, encoding))
+            .unwrap();
+    }
+
+    // Write a symbol map file, in the encoding of the source program unless it is JSON, which
+    // is always UTF-8
+    if let Some(map_path) = matches.value_of("map") {
+        let mut file = match File::create(map_path) {
+            Err(why) => panic!("{map_path}: {why}"),
+            Ok(file) => file,
+        };
+        let mut map = Vec::new();
+        match matches.value_of("map-format") {
+            Some("json") => rm1masm::write_map_json(&mut map, &object).unwrap(),
+            _ => {
+                rm1masm::write_map(&mut map, &object).unwrap();
+                map = encode(map_path, &map, encoding);
             }
-            let code = code.unwrap();
-
-            // Set a binary file name
-            let output_path = if let Some(output_file_name) = matches.value_of("output") {
-                PathBuf::from(output_file_name)
-            } else {
-                let mut output_path = PathBuf::from(input_path);
-                output_path.set_extension("o");
-                output_path
-            };
+        }
+        file.write_all(&map).unwrap();
+    }
+
+    // Write Makefile rules, with an empty one for each included file so that make goes on
+    // when the file is removed
+    if let Some(deps_path) = matches.value_of("deps") {
+        let mut file = match File::create(deps_path) {
+            Err(why) => panic!("{deps_path}: {why}"),
+            Ok(file) => file,
+        };
+        let dependencies = source_map.dependencies();
+        write!(
+            file,
+            "{}: {}",
+            make_path(&output_path),
+            make_path(Path::new(input_path))
+        )
+        .unwrap();
+        for dependency in &dependencies {
+            write!(file, " {}", make_path(dependency)).unwrap();
+        }
+        writeln!(file).unwrap();
+        for dependency in &dependencies {
+            writeln!(file, "\n{}:", make_path(dependency)).unwrap();
+        }
+    }
+}
 
-            // Open a binary file
-            let mut file = match File::create(&output_path) {
-                Err(why) => panic!("{}: {why}", output_path.display()),
+/// `path` as written in a Makefile rule
+fn make_path(path: &Path) -> String {
+    path.display()
+        .to_string()
+        .replace(' ', "\\ ")
+        .replace('$', "$$")
+}
+
+fn disasm(matches: &ArgMatches) {
+    // Read object file
+    let input_path = matches.value_of("input").unwrap();
+    let (object, encoding) = read_object_file(input_path, encoding(matches), 1);
+
+    // Disassemble the object
+    let source_program = match rm1masm::disassemble(&object) {
+        Ok(source_program) => source_program,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{input_path}: {error}");
+            }
+            std::process::exit(1);
+        }
+    };
+
+    // Write source program, in the encoding of the object
+    match matches.value_of("output") {
+        Some(output_path) => {
+            let source_program = encode(output_path, source_program.as_bytes(), encoding);
+            let mut file = match File::create(output_path) {
+                Err(why) => panic!("{output_path}: {why}"),
                 Ok(file) => file,
             };
+            file.write_all(&source_program).unwrap();
+        }
+        None => {
+            let source_program = encode("<stdout>", source_program.as_bytes(), encoding);
+            std::io::stdout().write_all(&source_program).unwrap();
+        }
+    }
+}
 
-            // Write a binary file
-            write!(file, "CM {}", ast.title).unwrap();
-            for (address, instruction) in code.iter() {
-                let instruction = instruction.resolve(&symbol_table).unwrap();
-                if let Ok(code) = MachineCode::try_from(&instruction) {
-                    write!(file, "\n{address:03X}  {code:010X}").unwrap();
-                } else {
-                    panic!("Unexpected instruction");
-                }
+fn sim(matches: &ArgMatches) {
+    // Read object file
+    let input_path = matches.value_of("input").unwrap();
+    let (object, _) = read_object_file(input_path, encoding(matches), 1);
+    let mut simulator = rm1masm::Simulator::new(&object);
+
+    // Load main memory
+    if let Some(memory_path) = matches.value_of("memory") {
+        match rm1masm::read_memory(&read_file(memory_path, encoding(matches), 1).0) {
+            Ok(words) => simulator.load_memory(&words),
+            Err(error) => {
+                eprintln!("{memory_path}: {error}");
+                std::process::exit(1);
             }
-        } else {
-            panic!("Illegal addres found.");
+        }
+    }
+
+    let cycles = match matches.value_of("cycles").unwrap().parse::<u64>() {
+        Ok(cycles) => cycles,
+        Err(_) => {
+            eprintln!("--cycles: not a number of cycles");
+            std::process::exit(1);
+        }
+    };
+
+    // Run the microprogram
+    let result = if matches.is_present("trace") {
+        loop {
+            if simulator.halted {
+                break Ok(());
+            }
+            if simulator.cycles >= cycles {
+                break Err(rm1masm::SimulateError::CycleLimit { cycles });
+            }
+            let address = simulator.address;
+            if let Err(error) = simulator.step() {
+                break Err(error);
+            }
+            println!("{address:03X}  {}", simulator.registers);
         }
     } else {
-        for err in errs {
-            Report::build(ReportKind::Error, input_path, err.span().start)
-                .with_message("Unexpected token")
-                .with_label(Label::new((input_path, err.span())).with_message(format!(
-                    "Unexpected token {}",
-                    &source_program[err.span()].fg(Color::Red)
-                )))
-                .finish()
-                .print((input_path, Source::from(&source_program)))
-                .unwrap();
+        simulator.run(cycles)
+    };
+
+    for word in &simulator.output {
+        println!("IO  {word:04X}");
+    }
+    println!("{}", simulator.registers);
+    if let Err(error) = result {
+        eprintln!("{input_path}: {error}");
+        std::process::exit(1);
+    }
+}
+
+fn diff(matches: &ArgMatches) {
+    // Read object files, with labels from their source code
+    let objects =
+        [("first", "first-source"), ("second", "second-source")].map(|(input, source)| {
+            let (mut object, _) =
+                read_object_file(matches.value_of(input).unwrap(), encoding(matches), 2);
+            if let Some(source_path) = matches.value_of(source) {
+                let (source_map, _) = read_source(source_path, matches, 2);
+                match rm1masm::assemble(source_map.text(), &options(matches)) {
+                    Ok(assembled) => object.symbols = assembled.symbols,
+                    Err(diagnostics) => {
+                        diagnostics.eprint_map(&source_map).unwrap();
+                        std::process::exit(2);
+                    }
+                }
+            }
+            object
+        });
+
+    // Compare the objects
+    let differences = rm1masm::diff(&objects[0], &objects[1]);
+    for difference in &differences {
+        println!("{difference}");
+    }
+    if !differences.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+fn explain(matches: &ArgMatches) {
+    let address =
+        match rm1masm::MachineAddress::from_str_radix(matches.value_of("address").unwrap(), 16) {
+            Ok(address) if address <= 0xFFF => address,
+            _ => {
+                eprintln!("--address: not a 12-bit address in hexadecimal");
+                std::process::exit(1);
+            }
+        };
+    let statements = matches
+        .values_of("instruction")
+        .unwrap()
+        .collect::<Vec<_>>();
+
+    let explanation = match statements[..] {
+        [word] if word.len() == 10 && word.chars().all(|c| c.is_ascii_hexdigit()) => {
+            rm1masm::explain(
+                address,
+                rm1masm::MachineCode::from_str_radix(word, 16).unwrap(),
+            )
+        }
+        _ => {
+            // Make a program of the instruction alone
+            let source_program = format!(
+                ".TITLE EXPLAIN\n* {address:03X}\n\t{}\n.END\n",
+                statements.join("\n\t")
+            );
+            match rm1masm::explain_source(&source_program, &options(matches)) {
+                Ok(explanation) => explanation,
+                Err(diagnostics) => {
+                    diagnostics
+                        .eprint("<instruction>", &source_program)
+                        .unwrap();
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+    print!("{explanation}");
+}
+
+/// Options for assembling, with the constants set by `-D`
+fn options(matches: &ArgMatches) -> rm1masm::Options {
+    let defines = matches
+        .values_of("define")
+        .map_or(Vec::new(), |definitions| {
+            definitions
+                .map(|definition| {
+                    let value = definition
+                        .split_once('=')
+                        .and_then(|(name, value)| Some((name, rm1masm::parse_value(value)?)));
+                    match value {
+                        Some((name, value)) if !name.is_empty() => (name.to_string(), value),
+                        _ => {
+                            eprintln!(
+                                "-D {definition}: not NAME=VALUE with VALUE a 16-bit expression"
+                            );
+                            std::process::exit(1);
+                        }
+                    }
+                })
+                .collect()
+        });
+    rm1masm::Options { defines }
+}
+
+/// Encoding set by `--encoding`, if any
+fn encoding(matches: &ArgMatches) -> Option<rm1masm::Encoding> {
+    matches
+        .value_of("encoding")
+        .map(|name| name.parse().unwrap())
+}
+
+/// Reads a text file in `encoding`, or in the one it is detected to be in, exiting with `code`
+/// if it cannot be read
+fn read_file(
+    path: &str,
+    encoding: Option<rm1masm::Encoding>,
+    code: i32,
+) -> (String, rm1masm::Encoding) {
+    let bytes = std::fs::read(path).unwrap_or_else(|why| {
+        eprintln!("{path}: {why}");
+        std::process::exit(code);
+    });
+    decode(&bytes, encoding).unwrap_or_else(|error| {
+        eprintln!("{path}: {error}");
+        std::process::exit(code);
+    })
+}
+
+/// Converts `bytes` to text in `encoding`, or in the one they are detected to be in
+fn decode(
+    bytes: &[u8],
+    encoding: Option<rm1masm::Encoding>,
+) -> Result<(String, rm1masm::Encoding), rm1masm::EncodingError> {
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => rm1masm::Encoding::detect(bytes)?,
+    };
+    encoding.decode(bytes).map(|text| (text, encoding))
+}
+
+/// Reads a source program with the files it includes, exiting with `code` if one cannot be
+/// read
+fn read_source(
+    path: &str,
+    matches: &ArgMatches,
+    code: i32,
+) -> (rm1masm::SourceMap, rm1masm::Encoding) {
+    let set_encoding = encoding(matches);
+    let (text, encoding) = read_file(path, set_encoding, code);
+    let include_paths = matches
+        .values_of("include")
+        .map_or(Vec::new(), |paths| paths.map(PathBuf::from).collect());
+    let source_map = rm1masm::SourceMap::load(Path::new(path), text, &include_paths, |path| {
+        let bytes = std::fs::read(path)?;
+        decode(&bytes, set_encoding)
+            .map(|(text, _)| text)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    });
+    if !source_map.errors().is_empty() {
+        rm1masm::Diagnostics::from_iter(source_map.errors().iter().cloned())
+            .eprint_map(&source_map)
+            .unwrap();
+        std::process::exit(code);
+    }
+    (source_map, encoding)
+}
+
+/// Converts UTF-8 `text` written for `path` to `encoding`, exiting if a character has no code
+fn encode(path: &str, text: &[u8], encoding: rm1masm::Encoding) -> Vec<u8> {
+    encoding
+        .encode(std::str::from_utf8(text).unwrap())
+        .unwrap_or_else(|error| {
+            eprintln!("{path}: {error}");
+            std::process::exit(1);
+        })
+}
+
+/// Reads a `CM` object, exiting with `code` if it is malformed
+fn read_object_file(
+    path: &str,
+    encoding: Option<rm1masm::Encoding>,
+    code: i32,
+) -> (rm1masm::Object, rm1masm::Encoding) {
+    let (text, encoding) = read_file(path, encoding, code);
+    match rm1masm::read_object(&text) {
+        Ok(object) => (object, encoding),
+        Err(error) => {
+            eprintln!("{path}: {error}");
+            std::process::exit(code);
         }
     }
 }
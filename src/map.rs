@@ -0,0 +1,67 @@
+use crate::symbol::Symbol;
+use crate::Object;
+
+use serde::Serialize;
+
+use std::io::{self, Write};
+
+/// Writes the symbols of `object` sorted by address, then sorted by name
+///
+/// Each line has the address, the label and the kinds of reference made to the label.
+pub fn write_map(out: &mut dyn Write, object: &Object) -> io::Result<()> {
+    let write_symbol = |out: &mut dyn Write, symbol: &Symbol| {
+        let references = symbol
+            .references
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let line = format!(
+            "{:03X}  {:<16} {}",
+            symbol.address,
+            symbol.name,
+            references.join(" ")
+        );
+        writeln!(out, "{}", line.trim_end())
+    };
+
+    writeln!(out, "{}", object.title)?;
+    writeln!(out)?;
+    writeln!(out, "BY ADDRESS")?;
+    for symbol in &object.symbols {
+        write_symbol(out, symbol)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "BY NAME")?;
+    for symbol in by_name(object) {
+        write_symbol(out, symbol)?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Map<'a> {
+    title: &'a str,
+    by_address: &'a [Symbol],
+    by_name: Vec<&'a Symbol>,
+}
+
+/// Writes the symbols of `object` as JSON
+///
+/// The object has the `title`, and the symbols sorted by address in `by_address` and by name in
+/// `by_name`. Each symbol has its `name`, its `address` and the kinds of reference made to it in
+/// `references`, such as `"IOP"`.
+pub fn write_map_json(out: &mut dyn Write, object: &Object) -> io::Result<()> {
+    let map = Map {
+        title: &object.title,
+        by_address: &object.symbols,
+        by_name: by_name(object),
+    };
+    serde_json::to_writer_pretty(&mut *out, &map)?;
+    writeln!(out)
+}
+
+fn by_name(object: &Object) -> Vec<&Symbol> {
+    let mut symbols = object.symbols.iter().collect::<Vec<_>>();
+    symbols.sort_by(|a, b| a.name.cmp(&b.name));
+    symbols
+}
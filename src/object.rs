@@ -0,0 +1,216 @@
+use crate::error::ReadError;
+use crate::parser::{MachineAddress, MachineCode};
+use crate::placement::MAX_ADDRESS;
+use crate::{Diagnostics, Object};
+
+use std::collections::HashMap;
+
+/// Digits of an address in a `CM` object
+const ADDRESS_DIGITS: usize = 3;
+
+/// Digits of a word in a `CM` object
+const WORD_DIGITS: usize = 10;
+
+/// Splits `line` at whitespace into fields with the column each one starts at
+fn fields(line: &str) -> Vec<(usize, &str)> {
+    let mut fields = Vec::new();
+    let mut start = None;
+    for (index, c) in line.char_indices().chain([(line.len(), ' ')]) {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some(index),
+            (Some(from), true) => {
+                fields.push((from + 1, &line[from..index]));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    fields
+}
+
+/// Parses a hexadecimal field
+fn hex(line: usize, (column, text): (usize, &str), field: &'static str) -> Result<u64, ReadError> {
+    match text.chars().all(|c| c.is_ascii_hexdigit()) && text.len() <= 16 {
+        true => Ok(u64::from_str_radix(text, 16).unwrap()),
+        false => Err(ReadError::InvalidHex {
+            line,
+            column,
+            field,
+        }),
+    }
+}
+
+/// Checks that a field has `expected` digits
+fn width(
+    line: usize,
+    (column, text): (usize, &str),
+    field: &'static str,
+    expected: usize,
+) -> Result<(), ReadError> {
+    match text.len() == expected {
+        true => Ok(()),
+        false => Err(ReadError::FieldWidth {
+            line,
+            column,
+            field,
+            digits: text.len(),
+            expected,
+        }),
+    }
+}
+
+/// Reads an object in the `CM` format written by the assembler
+///
+/// The first line is `CM` followed by the title, and each of the other lines has an address of
+/// 3 hexadecimal digits and a word of 10. Blank lines, and the end-of-file character that
+/// MS-DOS editors leave, are skipped. Every address must be within the control store and given
+/// only once.
+pub fn read_object(text: &str) -> Result<Object, ReadError> {
+    let mut lines = text.lines().enumerate();
+
+    let title = match lines.next() {
+        Some((_, "CM")) => String::new(),
+        Some((_, line)) if line.starts_with("CM ") || line.starts_with("CM\t") => {
+            line[2..].trim().to_string()
+        }
+        _ => return Err(ReadError::MissingHeader),
+    };
+
+    let mut words = Vec::<(MachineAddress, MachineCode)>::new();
+    let mut lines_by_address = HashMap::<MachineAddress, usize>::new();
+    for (index, line) in lines {
+        let line_number = index + 1;
+        if line
+            .trim_matches(|c: char| c.is_whitespace() || c == '\x1A')
+            .is_empty()
+        {
+            continue;
+        }
+        let (address_field, word_field) = match fields(line)[..] {
+            [address, word] => (address, word),
+            _ => return Err(ReadError::MalformedLine { line: line_number }),
+        };
+        let address = hex(line_number, address_field, "address")?;
+        if address > u64::from(MAX_ADDRESS) {
+            return Err(ReadError::AddressOutOfRange {
+                line: line_number,
+                column: address_field.0,
+                address: address.min(u64::from(u32::MAX)) as u32,
+            });
+        }
+        width(line_number, address_field, "address", ADDRESS_DIGITS)?;
+        let address = address as MachineAddress;
+        let code = hex(line_number, word_field, "word")?;
+        width(line_number, word_field, "word", WORD_DIGITS)?;
+
+        if let Some(&previous) = lines_by_address.get(&address) {
+            return Err(ReadError::DuplicateAddress {
+                line: line_number,
+                address,
+                previous,
+            });
+        }
+        lines_by_address.insert(address, line_number);
+        words.push((address, code));
+    }
+
+    Ok(Object {
+        title,
+        code: words,
+        spans: Vec::new(),
+        symbols: Vec::new(),
+        diagnostics: Diagnostics::default(),
+    })
+}
+
+/// Reads a main memory image
+///
+/// Each line has an address and a 16-bit word in hexadecimal. Blank lines are skipped.
+pub fn read_memory(text: &str) -> Result<Vec<(u16, u16)>, ReadError> {
+    let mut words = Vec::<(u16, u16)>::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let word = match (fields.next(), fields.next(), fields.next()) {
+            (Some(address), Some(word), None) => u16::from_str_radix(address, 16)
+                .ok()
+                .zip(u16::from_str_radix(word, 16).ok()),
+            _ => None,
+        };
+        match word {
+            Some(word) => words.push(word),
+            None => return Err(ReadError::MalformedLine { line: index + 1 }),
+        }
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_object;
+    use crate::error::ReadError;
+
+    #[test]
+    fn test_read_object() {
+        let object = read_object("CM SAMPLE\n000  AA1F0FFE01\n\n010  FFFFF8FE00\n\x1A").unwrap();
+        assert_eq!(object.title, "SAMPLE");
+        assert_eq!(
+            object.code,
+            vec![(0x000, 0xAA1F0FFE01), (0x010, 0xFFFFF8FE00)]
+        );
+
+        let errors = [
+            ("CMSAMPLE\n", ReadError::MissingHeader),
+            ("CM\n000\n", ReadError::MalformedLine { line: 2 }),
+            (
+                "CM\n000  AA1F0FFEG1\n",
+                ReadError::InvalidHex {
+                    line: 2,
+                    column: 6,
+                    field: "word",
+                },
+            ),
+            (
+                "CM\n00  AA1F0FFE01\n",
+                ReadError::FieldWidth {
+                    line: 2,
+                    column: 1,
+                    field: "address",
+                    digits: 2,
+                    expected: 3,
+                },
+            ),
+            (
+                "CM\n000 AA1F0FFE0\n",
+                ReadError::FieldWidth {
+                    line: 2,
+                    column: 5,
+                    field: "word",
+                    digits: 9,
+                    expected: 10,
+                },
+            ),
+            (
+                "CM\n 1000  AA1F0FFE01\n",
+                ReadError::AddressOutOfRange {
+                    line: 2,
+                    column: 2,
+                    address: 0x1000,
+                },
+            ),
+            (
+                "CM\n001  AA1F0FFE01\n001  FFFFF8FE00\n",
+                ReadError::DuplicateAddress {
+                    line: 3,
+                    address: 0x001,
+                    previous: 2,
+                },
+            ),
+        ];
+        for (text, error) in errors {
+            assert_eq!(read_object(text), Err(error));
+        }
+    }
+}
@@ -1,3 +1,8 @@
+use crate::constant::Constants;
+use crate::error::{Error, Location};
+use crate::expression::{self, BinaryOperator, Expression, UnaryOperator};
+use crate::placement;
+use crate::symbol::{self, ReferenceIndex, ReferenceKind};
 use crate::token::Token;
 
 use chumsky::prelude::*;
@@ -6,106 +11,51 @@ use logos::Span;
 
 pub type MachineAddress = u16;
 pub type MachineCode = u64;
+pub type Spanned<T> = (T, Span);
 
 pub struct Ast<'a> {
     pub title: &'a str,
     pub instructions: Vec<Instruction<'a>>,
 }
 
-fn is_refered_by_iop(instructions: &[Instruction], label: &str) -> bool {
-    instructions.iter().any(|instruction| {
-        instruction.test_and_sequence_statement == Some(TestAndSequenceStatement::Iop(label))
-    })
-}
-
-fn is_refered_by_ira(instructions: &[Instruction], label: &str) -> bool {
-    instructions.iter().any(|instruction| {
-        instruction.test_and_sequence_statement == Some(TestAndSequenceStatement::Ira(label))
-    })
-}
-
-fn is_refered_by_iab(instructions: &[Instruction], label: &str) -> bool {
-    instructions.iter().any(|instruction| {
-        instruction.test_and_sequence_statement == Some(TestAndSequenceStatement::Iab(label))
-    })
-}
-
 impl<'a> Ast<'a> {
-    pub fn set_address(&'a self) -> Result<Self, ()> {
-        let mut instructions = Vec::<Instruction>::new();
-
-        let mut address = 0;
-        for instruction in &self.instructions {
-            address = if let Some(label) = instruction.label {
-                if is_refered_by_iop(&self.instructions, label) {
-                    if let Some(constant) = instruction.address {
-                        if (constant & 0xF) == 0 {
-                            constant
-                        } else {
-                            return Err(());
-                        }
-                    } else {
-                        address += 0x10;
-                        address & 0xFF0
-                    }
-                } else if is_refered_by_ira(&self.instructions, label) {
-                    if let Some(constant) = instruction.address {
-                        if (constant & 0xC) == 0 {
-                            constant
-                        } else {
-                            return Err(());
-                        }
-                    } else {
-                        address += 0x10;
-                        address & 0xFF3
-                    }
-                } else if is_refered_by_iab(&self.instructions, label) {
-                    if let Some(constant) = instruction.address {
-                        if (constant & 0x3C) == 0 {
-                            constant
-                        } else {
-                            return Err(());
-                        }
-                    } else {
-                        address += 0x40;
-                        address & 0xFC0
-                    }
-                } else if let Some(constant) = instruction.address {
-                    constant
-                } else {
-                    address
-                }
-            } else if let Some(constant) = instruction.address {
-                constant
-            } else {
-                address
-            };
-
-            // let mut instruction = self.instructions[index].clone();
-            // instruction.address = Some(address);
-            instructions.push(Instruction {
-                address: Some(address),
-                ..instruction.clone()
-            });
-
-            address += 1;
-        }
-
-        Ok(Ast {
-            title: self.title,
-            instructions,
-        })
+    /// Places every instruction in the control store
+    pub fn set_address(mut self, references: &ReferenceIndex) -> (Self, Vec<Error>) {
+        let errors = placement::place(&mut self.instructions, references);
+        (self, errors)
     }
 }
 
 #[derive(Clone)]
 pub struct Instruction<'a> {
-    pub label: Option<&'a str>,
+    /// Span of the instruction head (`* LABEL: ADDRESS`)
+    pub span: Span,
+    pub label: Option<Spanned<&'a str>>,
+    /// Address written in the head, calculated by [`expression::evaluate_addresses`]
+    pub address_expression: Option<Spanned<Expression>>,
     pub address: Option<MachineAddress>,
-    pub test_and_sequence_statement: Option<TestAndSequenceStatement<'a>>,
-    pub memory_statement: Option<MemoryStatement>,
-    pub calculation_statement: Option<CalculationStatement>,
-    pub ex_statement: Option<ExStatement>,
+    pub test_and_sequence_statement: Option<Spanned<TestAndSequenceStatement<'a>>>,
+    pub memory_statement: Option<Spanned<MemoryStatement>>,
+    pub calculation_statement: Option<Spanned<CalculationStatement>>,
+    pub ex_statement: Option<Spanned<ExStatement>>,
+}
+
+impl<'a> Instruction<'a> {
+    pub fn location(&self) -> Location {
+        Location {
+            label: self.label.as_ref().map(|(label, _)| label.to_string()),
+            span: self.span.clone(),
+            address: self.address,
+        }
+    }
+
+    pub fn misaligned(&self, kind: ReferenceKind, mask: MachineAddress) -> Error {
+        Error::MisalignedAddress {
+            location: self.location(),
+            kind,
+            mask,
+        }
+    }
 }
 
 #[derive(Clone, Eq, PartialEq)]
@@ -231,7 +181,16 @@ pub enum Rbus {
     R7,
     Ra,
     Rap,
-    Literal(u16),
+    Literal(Literal),
+}
+
+/// Number put on the Rbus
+#[derive(Clone)]
+pub struct Literal {
+    pub expression: Spanned<Expression>,
+    /// Value of `expression`, calculated by [`expression::evaluate_literals`] once labels have
+    /// addresses
+    pub value: u16,
 }
 
 #[derive(Clone)]
@@ -278,22 +237,40 @@ pub enum RbusSource {
     Rbus,
 }
 
-fn parser<'a>() -> impl Parser<Token<'a>, Ast<'a>, Error = Simple<Token<'a>>> {
+#[allow(clippy::result_large_err)]
+fn parser<'a: 'b, 'b>(
+    constants: &'b Constants,
+) -> impl Parser<Token<'a>, Ast<'a>, Error = Simple<Token<'a>>> + 'b {
     let string = select! { Token::String(s) => s };
 
-    let label = string.then_ignore(just(Token::Colon)).or_not();
-    let unsigned_integer = select! {
-        Token::String(s) if u16::from_str_radix(s, 16).is_ok() => u16::from_str_radix(s, 16).unwrap(),
-        Token::Hexadecimal(h) => h,
-        Token::Decimal(d) => d,
-        Token::Binary(b) => b,
-    };
-    let literal = unsigned_integer;
-    let address = unsigned_integer;
-
+    let label = string
+        .map_with_span(|label, span| (label, span))
+        .then_ignore(just(Token::Colon))
+        .or_not();
+    let expression = expression(constants);
+    let literal = expression.clone().map(|expression| Literal {
+        expression,
+        value: 0,
+    });
+    let address = expression;
+
+    // `* LABEL ADDRESS` may leave out the colon, which `* LABEL` alone needs. A constant, or a
+    // name followed by `-`, starts an address there instead, so that `* BASE-1` and `* FF-1` are
+    // not read as a label at -1 as tokens carry no spacing
+    let bare_label = filter_map(move |span, token| match token {
+        Token::String(name) if constants.get(name).is_none() => Ok(name),
+        token => Err(Simple::expected_input_found(span, None, Some(token))),
+    })
+    .then_ignore(none_of([Token::Minus]).rewind());
     let instruction_head = just(Token::Star)
-        .ignore_then(label)
-        .then(address.or_not())
+        .ignore_then(
+            bare_label
+                .map_with_span(|label, span| (label, span))
+                .then(address.clone())
+                .map(|(label, address)| (Some(label), Some(address)))
+                .or(label.then(address.or_not())),
+        )
+        .map_with_span(|(label, address), span| (span, label, address))
         .then_ignore(just(Token::Eol));
 
     // Test and sequence statement
@@ -357,6 +334,7 @@ fn parser<'a>() -> impl Parser<Token<'a>, Ast<'a>, Error = Simple<Token<'a>>> {
         .or(ira)
         .or(irb)
         .or(nsq)
+        .map_with_span(|statement, span| (statement, span))
         .then_ignore(just(Token::Eol))
         .or_not();
 
@@ -365,6 +343,7 @@ fn parser<'a>() -> impl Parser<Token<'a>, Ast<'a>, Error = Simple<Token<'a>>> {
         just(Token::String("READ")).to(MemoryStatement::Read),
         just(Token::String("WRITE")).to(MemoryStatement::Write),
     ))
+    .map_with_span(|statement, span| (statement, span))
     .or_not();
 
     // calculation statement
@@ -438,14 +417,17 @@ fn parser<'a>() -> impl Parser<Token<'a>, Ast<'a>, Error = Simple<Token<'a>>> {
     let alu_through_statement = shift
         .or_not()
         .map(|shift| shift)
-        .then(lbus.map(LbusOrRbus::Lbus).or(rbus.map(LbusOrRbus::Rbus)))
+        .then(
+            lbus.map(LbusOrRbus::Lbus)
+                .or(rbus.clone().map(LbusOrRbus::Rbus)),
+        )
         .map(|(shift, lbus_or_rbus)| AluThrough {
             shift,
             lbus_or_rbus,
         });
     let statement = lbus
         .then(alu_and_shift)
-        .then(rbus)
+        .then(rbus.clone())
         .map(|((lbus, alu_and_shift), rbus)| Statement::First(lbus, alu_and_shift, rbus))
         .or(alu_through_statement.map(Statement::AluThrough));
     let alu_statement = sbus
@@ -458,6 +440,7 @@ fn parser<'a>() -> impl Parser<Token<'a>, Ast<'a>, Error = Simple<Token<'a>>> {
         .map(CalculationStatement::Set);
     let calculation_statement = alu_statement
         .or(set_statement)
+        .map_with_span(|statement, span| (statement, span))
         .then_ignore(just(Token::Eol))
         .or_not();
 
@@ -523,6 +506,7 @@ fn parser<'a>() -> impl Parser<Token<'a>, Ast<'a>, Error = Simple<Token<'a>>> {
             .ignore_then(just(Token::String("OV")))
             .to(ExStatement::SetOv))
         .or(just(Token::String("NEX")).to(ExStatement::Nex))
+        .map_with_span(|statement, span| (statement, span))
         .then_ignore(just(Token::Eol))
         .or_not();
 
@@ -533,43 +517,51 @@ fn parser<'a>() -> impl Parser<Token<'a>, Ast<'a>, Error = Simple<Token<'a>>> {
         .then(calculation_statement.then_ignore(eols.clone()))
         .then(ex_statement.then_ignore(eols.clone()));
 
-    let instruction = eols
-        .clone()
-        .ignore_then(instruction_head)
+    let instruction = instruction_head
         .then_ignore(eols.clone())
         .then(instruction_body)
-        .recover_with(skip_then_retry_until([Token::Star]));
+        .map(
+            |(
+                (span, label, address_expression),
+                (
+                    ((test_and_sequence_statement, memory_statement), calculation_statement),
+                    ex_statement,
+                ),
+            )| Instruction {
+                span,
+                label,
+                address_expression,
+                address: None,
+                test_and_sequence_statement,
+                memory_statement,
+                calculation_statement,
+                ex_statement,
+            },
+        );
+
+    // A line that does not fit into an instruction is reported, and the following lines up to
+    // the next instruction are skipped, so that the rest of the program is still checked
+    let junk = none_of([Token::DotString("END"), Token::Eol])
+        .repeated()
+        .at_least(1)
+        .validate(|tokens: Vec<Token>, span, emit| {
+            if !tokens.contains(&Token::Error) {
+                emit(Simple::expected_input_found(
+                    span,
+                    [Some(Token::Star)],
+                    tokens.into_iter().next(),
+                ))
+            }
+        })
+        .then_ignore(none_of([Token::Star, Token::DotString("END")]).repeated());
 
     let program_title = just(Token::DotString("TITLE")).ignore_then(string);
     let program_body = eols
         .clone()
-        .ignore_then(instruction)
+        .ignore_then(instruction.map(Some).or(junk.to(None)))
         .repeated()
         .at_least(1)
-        .map(|instructions| {
-            instructions
-                .into_iter()
-                .map(
-                    |(
-                        (label, address),
-                        (
-                            (
-                                (test_and_sequence_statement, memory_statement),
-                                calculation_statement,
-                            ),
-                            ex_statement,
-                        ),
-                    )| Instruction {
-                        label,
-                        address,
-                        test_and_sequence_statement,
-                        memory_statement,
-                        calculation_statement,
-                        ex_statement,
-                    },
-                )
-                .collect()
-        });
+        .map(|instructions| instructions.into_iter().flatten().collect());
 
     let program = program_title
         .then(program_body.delimited_by(just(Token::Eol), just(Token::DotString("END"))))
@@ -582,6 +574,159 @@ fn parser<'a>() -> impl Parser<Token<'a>, Ast<'a>, Error = Simple<Token<'a>>> {
     })
 }
 
-pub fn parse(tokens: Vec<(Token, Span)>) -> (Option<Ast>, Vec<Simple<Token>>) {
-    parser().parse_recovery(Stream::from_iter(0..tokens.len(), tokens.into_iter()))
+/// Expression, with the names in `constants` read as their values
+#[allow(clippy::result_large_err)]
+fn expression<'a: 'b, 'b>(
+    constants: &'b Constants,
+) -> impl Parser<Token<'a>, Spanned<Expression>, Error = Simple<Token<'a>>> + Clone + 'b {
+    // a constant is read before a name that looks hexadecimal
+    let constant = filter_map(move |span, token| match token {
+        Token::String(name) if constants.get(name).is_some() => Ok(Expression::Constant(
+            name.to_string(),
+            constants.get(name).unwrap(),
+        )),
+        token => Err(Simple::expected_input_found(span, None, Some(token))),
+    });
+    let unsigned_integer = select! {
+        Token::String(s) if u16::from_str_radix(s, 16).is_ok() => u16::from_str_radix(s, 16).unwrap(),
+        Token::Hexadecimal(h) => h,
+        Token::Decimal(d) => d,
+        Token::Binary(b) => b,
+    };
+
+    // An expression never runs past the end of its line, so `*` and `-` are read as operators
+    // only after an operand
+    let name = select! {
+        Token::String(s) if !symbol::KEYWORDS.contains(&s) && !symbol::REGISTERS.contains(&s) => s,
+    };
+    recursive(|expression| {
+        let parenthesized = expression
+            .delimited_by(just(Token::LeftParen), just(Token::RightParen))
+            .boxed();
+        let extractor = select! {
+            Token::String("HIGH") => UnaryOperator::High,
+            Token::String("LOW") => UnaryOperator::Low,
+        }
+        .then(parenthesized.clone())
+        .map(|(operator, operand)| Expression::Unary(operator, Box::new(operand)));
+        let operand = constant
+            .or(unsigned_integer.map(Expression::Number))
+            .or(extractor)
+            .or(name.map(|name| Expression::Label(name.to_string())))
+            .map_with_span(|expression, span| (expression, span))
+            .or(parenthesized);
+        let unary = select! {
+            Token::Minus => UnaryOperator::Negate,
+            Token::Tilde => UnaryOperator::Not,
+        }
+        .map_with_span(|operator, span| (operator, span))
+        .repeated()
+        .then(operand)
+        .foldr(|(operator, span), operand| {
+            let span = expression::join(&span, &operand.1);
+            (Expression::Unary(operator, Box::new(operand)), span)
+        })
+        .boxed();
+        let product = binary(
+            unary,
+            select! {
+                Token::Star => BinaryOperator::Multiply,
+                Token::Slash => BinaryOperator::Divide,
+            },
+        );
+        let sum = binary(
+            product,
+            select! {
+                Token::Plus => BinaryOperator::Add,
+                Token::Minus => BinaryOperator::Subtract,
+            },
+        );
+        let shift = binary(
+            sum,
+            select! {
+                Token::LessLess => BinaryOperator::ShiftLeft,
+                Token::GreaterGreater => BinaryOperator::ShiftRight,
+            },
+        );
+        let and = binary(shift, just(Token::Ampersand).to(BinaryOperator::And));
+        binary(and, just(Token::Bar).to(BinaryOperator::Or))
+    })
+}
+
+/// Operands joined by operators of one precedence, from left to right
+fn binary<'a, 'b>(
+    operand: impl Parser<Token<'a>, Spanned<Expression>, Error = Simple<Token<'a>>> + Clone + 'b,
+    operator: impl Parser<Token<'a>, BinaryOperator, Error = Simple<Token<'a>>> + Clone + 'b,
+) -> BoxedParser<'b, Token<'a>, Spanned<Expression>, Simple<Token<'a>>> {
+    operand
+        .clone()
+        .then(operator.then(operand).repeated())
+        .foldl(|left, (operator, right)| {
+            let span = expression::join(&left.1, &right.1);
+            (
+                Expression::Binary(operator, Box::new(left), Box::new(right)),
+                span,
+            )
+        })
+        .boxed()
+}
+
+pub fn parse<'a>(
+    tokens: Vec<(Token<'a>, Span)>,
+    constants: &Constants,
+) -> (Option<Ast<'a>>, Vec<Simple<Token<'a>>>) {
+    parser(constants).parse_recovery(Stream::from_iter(0..tokens.len(), tokens.into_iter()))
+}
+
+/// Parses `tokens` as one expression, such as the value of a constant
+pub fn parse_expression<'a>(
+    tokens: Vec<(Token<'a>, Span)>,
+    constants: &Constants,
+) -> Result<Spanned<Expression>, Vec<Simple<Token<'a>>>> {
+    let eoi = tokens.last().map_or(0, |(_, span)| span.end);
+    expression(constants)
+        .then_ignore(end())
+        .parse(Stream::from_iter(eoi..eoi, tokens.into_iter()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::constant::Constants;
+    use crate::lexer::tokenize;
+
+    #[test]
+    fn test_spans() {
+        let source = ".TITLE T\n* LOOP: 010\n\tGOTO LOOP\n\tREAD\n\tR0 := R0 + 1\n\tC - 1\n.END\n";
+        let span = |text: &str| {
+            let start = source.find(text).unwrap();
+            start..start + text.len()
+        };
+        let (ast, errors) = parse(tokenize(source), &Constants::default());
+        assert!(errors.is_empty());
+        let instruction = &ast.unwrap().instructions[0];
+        assert_eq!(instruction.span, span("* LOOP: 010"));
+        assert_eq!(instruction.label, Some(("LOOP", span("LOOP"))));
+        let spans = [
+            instruction
+                .test_and_sequence_statement
+                .as_ref()
+                .map(|(_, span)| span),
+            instruction.memory_statement.as_ref().map(|(_, span)| span),
+            instruction
+                .calculation_statement
+                .as_ref()
+                .map(|(_, span)| span),
+            instruction.ex_statement.as_ref().map(|(_, span)| span),
+        ];
+        assert_eq!(
+            spans,
+            [
+                Some(&span("GOTO LOOP")),
+                Some(&span("READ")),
+                Some(&span("R0 := R0 + 1")),
+                Some(&span("C - 1")),
+            ]
+        );
+    }
 }
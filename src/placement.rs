@@ -0,0 +1,265 @@
+use crate::error::Error;
+use crate::parser::{Instruction, MachineAddress, TestAndSequenceStatement};
+use crate::symbol::{ReferenceIndex, ReferenceKind, SymbolTable};
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Highest address of the control store
+pub const MAX_ADDRESS: MachineAddress = 0xFFF;
+
+/// Bits of an address that a conditional branch takes from its own address
+const PAGE_MASK: MachineAddress = 0xE00;
+
+/// Checks that the placed instructions fit in the control store
+///
+/// Reports instructions sharing an address, addresses beyond 12 bits, and conditional
+/// branches to another 512-word page, which only encode the 9 LSBs of their target.
+pub fn verify(instructions: &[Instruction], symbol_table: &SymbolTable) -> Vec<Error> {
+    let mut errors = Vec::<Error>::new();
+    let mut occupied = HashMap::<MachineAddress, &Instruction>::new();
+
+    for instruction in instructions {
+        let address = match instruction.address {
+            Some(address) => address,
+            None => continue,
+        };
+
+        if address > MAX_ADDRESS {
+            errors.push(Error::AddressOutOfRange {
+                location: instruction.location(),
+            });
+            continue;
+        }
+
+        match occupied.get(&address) {
+            Some(previous) => errors.push(Error::AddressCollision {
+                location: instruction.location(),
+                previous: previous.location(),
+            }),
+            None => {
+                occupied.insert(address, instruction);
+            }
+        }
+
+        if let Some((TestAndSequenceStatement::If(_, _, symbol, _), span)) =
+            &instruction.test_and_sequence_statement
+        {
+            if let Some(&target) = symbol_table.get(symbol) {
+                if target <= MAX_ADDRESS && (target & PAGE_MASK) != (address & PAGE_MASK) {
+                    errors.push(Error::UnreachableTarget {
+                        location: instruction.location(),
+                        span: span.clone(),
+                        symbol: symbol.to_string(),
+                        target,
+                    });
+                }
+            }
+        }
+    }
+
+    errors
+}
+
+/// Straight-line run of instructions that must occupy consecutive addresses
+struct Block {
+    /// Index of the first instruction
+    start: usize,
+    len: usize,
+    /// Address of the first instruction, if an explicit address pins the block
+    base: Option<usize>,
+    /// Dispatch targets in the block as `(offset, kind)`
+    targets: Vec<(usize, ReferenceKind)>,
+}
+
+impl Block {
+    fn new(start: usize, base: Option<usize>) -> Self {
+        Block {
+            start,
+            len: 0,
+            base,
+            targets: Vec::new(),
+        }
+    }
+
+    fn is_aligned_at(&self, base: usize) -> bool {
+        self.targets.iter().all(|(offset, kind)| {
+            let mask = kind.dispatch_mask().unwrap() as usize;
+            (base + offset) & mask == 0
+        })
+    }
+}
+
+/// Whether execution can continue with the next instruction in the control store
+fn falls_through(instruction: &Instruction) -> bool {
+    !matches!(
+        &instruction.test_and_sequence_statement,
+        Some((
+            TestAndSequenceStatement::Goto(_)
+                | TestAndSequenceStatement::Return
+                | TestAndSequenceStatement::If(_, _, _, true)
+                | TestAndSequenceStatement::Iop(_)
+                | TestAndSequenceStatement::Ira(_)
+                | TestAndSequenceStatement::Iab(_),
+            _
+        ))
+    )
+}
+
+/// Splits the program into blocks
+///
+/// A block ends after an instruction that does not fall through. The words that a dispatch
+/// target can branch to are kept in one block, so that a jump table written in source order
+/// stays in that order. An explicit address always starts a new block pinned there, as it
+/// sets the location counter; the block never extends back over the instructions before it.
+fn split_into_blocks(instructions: &[Instruction], references: &ReferenceIndex) -> Vec<Block> {
+    let mut blocks = Vec::<Block>::new();
+    let base = |instruction: &Instruction| instruction.address.map(usize::from);
+    let mut block = Block::new(0, instructions.first().and_then(base));
+    // last instruction of the jump table being laid out
+    let mut table_end = 0;
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        let split = index > 0
+            && (instruction.address.is_some()
+                || (!falls_through(&instructions[index - 1]) && index > table_end));
+        if split {
+            blocks.push(std::mem::replace(
+                &mut block,
+                Block::new(index, base(instruction)),
+            ));
+        }
+
+        if let Some((label, _)) = &instruction.label {
+            for kind in references.kinds(label) {
+                if let Some(mask) = kind.dispatch_mask() {
+                    block.targets.push((index - block.start, kind));
+                    table_end = table_end.max(index + (mask as usize | 0x3));
+                }
+            }
+        }
+        block.len += 1;
+    }
+    if !instructions.is_empty() {
+        blocks.push(block);
+    }
+
+    blocks
+}
+
+/// Finds the lowest free address at or after `from` where `block` fits, wrapping around once
+fn find_space(block: &Block, occupied: &[bool], from: usize) -> Option<usize> {
+    let search = |range: Range<usize>| {
+        let mut base = range.start;
+        while base < range.end && base + block.len <= occupied.len() {
+            if !block.is_aligned_at(base) {
+                base += 1;
+                continue;
+            }
+            // skip every base that would overlap the last occupied word
+            match occupied[base..base + block.len]
+                .iter()
+                .rposition(|used| *used)
+            {
+                Some(offset) => base += offset + 1,
+                None => return Some(base),
+            }
+        }
+        None
+    };
+
+    search(from..occupied.len()).or_else(|| search(0..from))
+}
+
+/// Address from which a dispatch target following `counter` is looked for
+///
+/// This is where the location counter used to put it, skipping ahead and clearing the bits the
+/// dispatch fills in, so that existing programs keep their addresses.
+fn table_start(kind: ReferenceKind, counter: usize) -> usize {
+    match kind {
+        ReferenceKind::Iop => (counter + 0x10) & 0xFF0,
+        ReferenceKind::Ira => (counter + 0x10) & 0xFF3,
+        _ => (counter + 0x40) & 0xFC0,
+    }
+}
+
+/// Assigns an address to every instruction
+///
+/// Blocks pinned by explicit addresses are reserved first. The other blocks are then placed
+/// in source order, each at the first free space after the previous block, or after where the
+/// location counter puts its first dispatch target, such that its dispatch targets are aligned.
+/// Blocks that fit nowhere are reported and left without an address.
+pub fn place(instructions: &mut [Instruction], references: &ReferenceIndex) -> Vec<Error> {
+    let mut errors = Vec::<Error>::new();
+    let blocks = split_into_blocks(instructions, references);
+    let mut occupied = vec![false; usize::from(MAX_ADDRESS) + 1];
+
+    for block in &blocks {
+        if let Some(base) = block.base {
+            for address in base..base + block.len {
+                if let Some(used) = occupied.get_mut(address) {
+                    *used = true;
+                }
+            }
+        }
+    }
+
+    let mut counter = 0;
+    for block in &blocks {
+        let base = match block.base {
+            Some(base) => Some(base),
+            None => {
+                // the table may wrap around to 000, so the start is taken modulo the store
+                let from = match block.targets.first() {
+                    Some((offset, kind)) => {
+                        table_start(*kind, counter + offset).wrapping_sub(*offset)
+                            & usize::from(MAX_ADDRESS)
+                    }
+                    None => counter,
+                };
+                let base = find_space(block, &occupied, from);
+                if let Some(base) = base {
+                    occupied[base..base + block.len].fill(true);
+                }
+                base
+            }
+        };
+
+        let range = block.start..block.start + block.len;
+        match base {
+            Some(base) => {
+                for (offset, instruction) in instructions[range].iter_mut().enumerate() {
+                    instruction.address = Some((base + offset) as MachineAddress);
+                }
+                counter = base + block.len;
+            }
+            None => {
+                for instruction in instructions[range].iter_mut() {
+                    instruction.address = None;
+                }
+                errors.push(Error::Unplaceable {
+                    location: instructions[block.start].location(),
+                    words: block.len,
+                    alignment: block
+                        .targets
+                        .iter()
+                        .map(|(offset, kind)| (*offset, kind.dispatch_mask().unwrap()))
+                        .collect(),
+                });
+            }
+        }
+
+        // explicit addresses that break an alignment
+        if block.base.is_some() {
+            for (offset, kind) in &block.targets {
+                let instruction = &instructions[block.start + offset];
+                let mask = kind.dispatch_mask().unwrap();
+                if instruction.address.unwrap() & mask != 0 {
+                    errors.push(instruction.misaligned(*kind, mask));
+                }
+            }
+        }
+    }
+
+    errors
+}
@@ -0,0 +1,439 @@
+use crate::decode::*;
+use crate::error::SimulateError;
+use crate::parser::{MachineAddress, MachineCode};
+use crate::placement::MAX_ADDRESS;
+use crate::Object;
+
+use std::fmt;
+
+/// Bits of FSR that `FLAG SAVE` copies the flags to
+const FSR_ZER: u16 = 0x8;
+const FSR_NEG: u16 = 0x4;
+const FSR_CRY: u16 = 0x2;
+const FSR_OV: u16 = 0x1;
+
+/// Registers of the MICRO-1 data path
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Registers {
+    pub r: [u16; 8],
+    pub pc: u16,
+    pub ir: u16,
+    pub io: u16,
+    /// Word read from main memory
+    pub mm: u16,
+    pub fsr: u16,
+    pub c: u16,
+    pub t: bool,
+    pub zer: bool,
+    pub neg: bool,
+    pub cry: bool,
+    pub ov: bool,
+}
+
+impl Registers {
+    fn op(&self) -> u16 {
+        self.ir >> 12
+    }
+
+    fn ma(&self) -> u16 {
+        (self.ir >> 10) & 0x3
+    }
+
+    fn ra(&self) -> usize {
+        usize::from((self.ir >> 7) & 0x7)
+    }
+
+    fn mb(&self) -> u16 {
+        (self.ir >> 5) & 0x3
+    }
+
+    fn rb(&self) -> usize {
+        usize::from((self.ir >> 2) & 0x7)
+    }
+
+    /// Replaces the 3-bit field of IR at `shift` by `f` of its value
+    fn update_ir(&mut self, shift: u16, f: impl Fn(u16) -> u16) {
+        let field = f((self.ir >> shift) & 0x7) & 0x7;
+        self.ir = (self.ir & !(0x7 << shift)) | (field << shift);
+    }
+
+    fn flags(&self) -> u16 {
+        [
+            (self.zer, FSR_ZER),
+            (self.neg, FSR_NEG),
+            (self.cry, FSR_CRY),
+            (self.ov, FSR_OV),
+        ]
+        .iter()
+        .filter(|(flag, _)| *flag)
+        .fold(0, |fsr, (_, bit)| fsr | bit)
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, r) in self.r.iter().enumerate() {
+            write!(f, "R{index}={r:04X} ")?;
+        }
+        write!(
+            f,
+            "PC={:04X} IR={:04X} IO={:04X} MM={:04X} FSR={:04X} C={:04X} T={} {}{}{}{}",
+            self.pc,
+            self.ir,
+            self.io,
+            self.mm,
+            self.fsr,
+            self.c,
+            u8::from(self.t),
+            if self.zer { 'Z' } else { '-' },
+            if self.neg { 'N' } else { '-' },
+            if self.cry { 'C' } else { '-' },
+            if self.ov { 'V' } else { '-' },
+        )
+    }
+}
+
+/// Cycle-by-cycle simulator of a MICRO-1 control store
+///
+/// Every word reads the registers as they were at the start of its cycle, and writes them at
+/// the end. The model takes these choices where the word format leaves them open:
+///
+/// - IR holds OP in bits 15–12, MA in 11–10, RA in 9–7, MB in 6–5 and RB in 4–2; RAP and RBP
+///   are the registers numbered one above RA and RB.
+/// - IOP branches to its target ORed with OP, IRA with MA << 2, and IAB with MA << 4 and
+///   MB << 2. `GOTO FETCH` and the `ELSE` of an `IF` go to address 000.
+/// - `READ` loads MM with the word of main memory addressed by the Lbus, and `WRITE` stores the
+///   Rbus there.
+/// - Every ALU function but `@` sets the flags that the next words test; `@` passes
+///   Lbus OR Rbus through. `$` is the complement of the Lbus.
+/// - `EXECUTE IO` appends IO to [`Simulator::output`], and `SET HLT` stops the machine.
+#[derive(Clone, Debug)]
+pub struct Simulator {
+    pub registers: Registers,
+    /// Address of the next word to execute
+    pub address: MachineAddress,
+    /// Main memory of 64K words
+    pub memory: Vec<u16>,
+    /// Words written out by `EXECUTE IO`
+    pub output: Vec<u16>,
+    /// Number of words executed
+    pub cycles: u64,
+    pub halted: bool,
+    control_store: Vec<Option<MachineCode>>,
+    stack: Vec<MachineAddress>,
+}
+
+impl Simulator {
+    /// Loads `object` into the control store, with the registers and main memory cleared
+    pub fn new(object: &Object) -> Self {
+        let mut control_store = vec![None; usize::from(MAX_ADDRESS) + 1];
+        for &(address, code) in &object.code {
+            if let Some(word) = control_store.get_mut(usize::from(address)) {
+                *word = Some(code);
+            }
+        }
+
+        Simulator {
+            registers: Registers::default(),
+            address: 0x000,
+            memory: vec![0; 0x10000],
+            output: Vec::new(),
+            cycles: 0,
+            halted: false,
+            control_store,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Stores `words` into main memory
+    pub fn load_memory(&mut self, words: &[(u16, u16)]) {
+        for &(address, word) in words {
+            self.memory[usize::from(address)] = word;
+        }
+    }
+
+    /// Word that the next cycle executes
+    pub fn word(&self) -> Option<MachineCode> {
+        self.control_store
+            .get(usize::from(self.address))
+            .copied()
+            .flatten()
+    }
+
+    /// Executes one word
+    pub fn step(&mut self) -> Result<(), SimulateError> {
+        let address = self.address;
+        let code = self.word().ok_or(SimulateError::EmptyWord { address })?;
+        let fields = Fields::decode(address, code);
+        let invalid = |field| SimulateError::InvalidField {
+            address,
+            code,
+            field,
+        };
+        let registers = &self.registers;
+        let ex = fields.ex.unwrap_or(EX_NEX);
+
+        let lbus = match fields.lb {
+            lb @ 0..=7 => registers.r[usize::from(lb)],
+            LB_RB => registers.r[registers.rb()],
+            LB_RBP => registers.r[(registers.rb() + 1) & 0x7],
+            LB_PC => registers.pc,
+            LB_IO => registers.io,
+            LB_MM => registers.mm,
+            LB_IR => registers.ir,
+            LB_FSR => registers.fsr,
+            _ => 0,
+        };
+        let rbus = match fields.rb {
+            rb @ 0..=7 => registers.r[usize::from(rb)],
+            RB_RA => registers.r[registers.ra()],
+            RB_RAP => registers.r[(registers.ra() + 1) & 0x7],
+            RB_SLT | RB_LLT => fields.literal.unwrap(),
+            RB_NRB => 0,
+            _ => return Err(invalid("RB")),
+        };
+
+        // ALU, with the carry and the overflow of an addition
+        let carry_in = match ex {
+            EX_ASC => u32::from(registers.cry),
+            EX_AS1 => 1,
+            _ => 0,
+        };
+        let add = |l: u16, r: u16, carry_in: u32| {
+            let sum = u32::from(l) + u32::from(r) + carry_in;
+            let value = sum as u16;
+            (value, sum > 0xFFFF, (l ^ value) & (r ^ value) & 0x8000 != 0)
+        };
+        let (value, cry, ov) = match fields.al {
+            AL_ADD => add(lbus, rbus, carry_in),
+            AL_SUB => add(lbus, !rbus, if ex == EX_ASC { carry_in } else { 1 }),
+            AL_AND => (lbus & rbus, false, false),
+            AL_OR => (lbus | rbus, false, false),
+            AL_XOR => (lbus ^ rbus, false, false),
+            AL_IAL => (!lbus, false, false),
+            AL_NAL => (lbus | rbus, registers.cry, registers.ov),
+            _ => return Err(invalid("AL")),
+        };
+        let (value, cry) = match fields.sh {
+            SH_SLL => (value << 1, value & 0x8000 != 0),
+            SH_SRL => (value >> 1, value & 0x1 != 0),
+            SH_SLA => (
+                (value & 0x8000) | ((value << 1) & 0x7FFF),
+                value & 0x4000 != 0,
+            ),
+            SH_SRA => (((value as i16) >> 1) as u16, value & 0x1 != 0),
+            SH_SNX => (((value & 0x0F0F) << 4) | ((value & 0xF0F0) >> 4), cry),
+            SH_SWP => (value.rotate_left(8), cry),
+            SH_NSH => (value, cry),
+            _ => return Err(invalid("SH")),
+        };
+
+        let condition = match fields.ts.unwrap_or(TS_NTS) {
+            TS_ZER => registers.zer,
+            TS_NEG => registers.neg,
+            TS_CRY => registers.cry,
+            TS_OV => registers.ov,
+            TS_T => registers.t,
+            TS_CZ => registers.c == 0,
+            TS_NTS => false,
+            _ => return Err(invalid("TS")),
+        };
+        let next = (address + 1) & MAX_ADDRESS;
+        // a dispatch field can decode to a table beyond the control store
+        let target = || {
+            fields
+                .target
+                .filter(|target| *target <= MAX_ADDRESS)
+                .ok_or_else(|| invalid("SQ"))
+        };
+        let next = match fields.sq {
+            SQ_B => target()?,
+            SQ_BP => {
+                self.stack.push(next);
+                target()?
+            }
+            SQ_RTN => self
+                .stack
+                .pop()
+                .ok_or(SimulateError::StackUnderflow { address })?,
+            SQ_BT if condition => target()?,
+            SQ_BF if !condition => target()?,
+            SQ_BT | SQ_BF => next,
+            SQ_IOP => target()? | registers.op(),
+            SQ_IRA => target()? | (registers.ma() << 2),
+            SQ_IAB => target()? | (registers.ma() << 4) | (registers.mb() << 2),
+            SQ_EI if fields.ts.unwrap_or(TS_NTS) != TS_NTS && !condition => target()?,
+            SQ_EI => 0x000,
+            SQ_NSQ => next,
+            _ => return Err(invalid("SQ")),
+        };
+
+        let (ra, rb) = (registers.ra(), registers.rb());
+        let registers = &mut self.registers;
+        match fields.sb {
+            sb @ 0..=7 => registers.r[usize::from(sb)] = value,
+            SB_SA => registers.r[ra] = value,
+            SB_SAP => registers.r[(ra + 1) & 0x7] = value,
+            SB_SB => registers.r[rb] = value,
+            SB_SBP => registers.r[(rb + 1) & 0x7] = value,
+            SB_PCS => registers.pc = value,
+            SB_NSB => (),
+            _ => return Err(invalid("SB")),
+        }
+        if fields.al != AL_NAL {
+            registers.zer = value == 0;
+            registers.neg = value & 0x8000 != 0;
+            registers.cry = cry;
+            registers.ov = ov;
+        }
+
+        match fields.mm {
+            MM_RM => registers.mm = self.memory[usize::from(lbus)],
+            MM_WM => self.memory[usize::from(lbus)] = rbus,
+            MM_NMM => (),
+            _ => return Err(invalid("MM")),
+        }
+
+        match ex {
+            EX_CM1 => registers.c = registers.c.wrapping_sub(1),
+            EX_FLS => registers.fsr = registers.flags(),
+            EX_LIR => registers.ir = lbus,
+            EX_LIO => registers.io = lbus,
+            EX_SC => registers.c = rbus,
+            EX_EIO => self.output.push(registers.io),
+            EX_ST => registers.t = true,
+            EX_RT => registers.t = false,
+            EX_INA => registers.update_ir(7, |ra| ra + 1),
+            EX_INB => registers.update_ir(2, |rb| rb + 1),
+            EX_DCB => registers.update_ir(2, |rb| rb.wrapping_sub(1)),
+            EX_HLT => self.halted = true,
+            EX_OV => registers.ov = true,
+            _ => (),
+        }
+
+        self.address = next;
+        self.cycles += 1;
+        Ok(())
+    }
+
+    /// Executes words until `SET HLT`, giving up after `limit` cycles
+    pub fn run(&mut self, limit: u64) -> Result<(), SimulateError> {
+        while !self.halted {
+            if self.cycles >= limit {
+                return Err(SimulateError::CycleLimit { cycles: limit });
+            }
+            self.step()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Simulator;
+    use crate::error::SimulateError;
+    use crate::{assemble, read_object, Options};
+
+    // Adds the words from address 0010 up to the first zero into R1
+    const SUM: &str = "\
+.TITLE SUM
+* FETCH: 000
+\tR0 := 10
+*
+\tR1 := ZERO
+* LOOP:
+\tREAD
+\tR0 := R0 + 1
+*
+\tSET BY MM
+*
+\tIF ZER = 1 THEN DONE
+*
+\tR1 := MM + R1
+*
+\tGOTO LOOP
+* DONE:
+\tIO := R1
+*
+\tEXECUTE IO
+*
+\tSET HLT
+*
+\tGOTO DONE
+.END
+";
+
+    #[test]
+    fn test_run() {
+        let object = assemble(SUM, &Options::default()).unwrap();
+        let mut simulator = Simulator::new(&object);
+        simulator.load_memory(&[(0x10, 0x0001), (0x11, 0x0020), (0x12, 0x0300)]);
+        simulator.run(1000).unwrap();
+
+        assert_eq!(simulator.registers.r[1], 0x0321);
+        assert_eq!(simulator.output, vec![0x0321]);
+        assert!(simulator.registers.zer);
+    }
+
+    const DISPATCH: &str = "\
+.TITLE DISPATCH
+* FETCH: 000
+\tREAD
+\tPC := PC + 1
+*
+\tIR := MM
+*
+\tIOP OPS
+* OPS: 010
+\tCALL INC
+*
+\tRETURN
+*
+\tRA := RBP XOR RAP
+\tSET HLT
+* INC:
+\tRB := RB + 1
+\tIRB + 1
+*
+\tGOTO FETCH
+.END
+";
+
+    #[test]
+    fn test_dispatch() {
+        let object = assemble(DISPATCH, &Options::default()).unwrap();
+        let mut simulator = Simulator::new(&object);
+        // OP 0 increments R3, then OP 2 with RA = 5 and RB = 4 sets R5 to R5 XOR R6
+        simulator.load_memory(&[(0x0, 0x000C), (0x1, 0x2290)]);
+        simulator.registers.r[5] = 0x00F0;
+        simulator.registers.r[6] = 0x0FF0;
+        simulator.run(100).unwrap();
+
+        assert_eq!(simulator.registers.r[3], 0x0001);
+        assert_eq!(simulator.registers.ir, 0x2290);
+        assert_eq!(simulator.registers.r[5], 0x0F00);
+        assert_eq!(simulator.cycles, 10);
+
+        let mut simulator = Simulator::new(&object);
+        simulator.load_memory(&[(0x0, 0x1000)]);
+        assert_eq!(
+            simulator.run(100),
+            Err(SimulateError::StackUnderflow { address: 0x011 })
+        );
+    }
+
+    #[test]
+    fn test_invalid_target() {
+        // IOP with a 9-bit field that puts the table at 1FF0
+        let object = read_object("CM T\n000  FFFFF5FFF0\n").unwrap();
+        let mut simulator = Simulator::new(&object);
+        assert_eq!(
+            simulator.step(),
+            Err(SimulateError::InvalidField {
+                address: 0x000,
+                code: 0xFFFFF5FFF0,
+                field: "SQ"
+            })
+        );
+    }
+}
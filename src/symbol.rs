@@ -1,63 +1,258 @@
+use crate::error::{Error, Warning};
 use crate::parser::{Instruction, MachineAddress, TestAndSequenceStatement};
 
+use logos::Span;
+use serde::Serialize;
+
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 pub type SymbolTable<'a> = HashMap<&'a str, MachineAddress>;
 
-pub fn create_symbol_table<'a>(instructions: &'a Vec<Instruction>) -> SymbolTable<'a> {
-    let mut symbol_table = SymbolTable::new();
+/// Label of an assembled microprogram
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Symbol {
+    pub name: String,
+    pub address: MachineAddress,
+    /// Kinds of reference made to the label, in the order of [`ReferenceKind`]
+    pub references: Vec<ReferenceKind>,
+}
 
-    for instruction in instructions {
-        if let Some(label) = instruction.label {
-            if let Some(address) = instruction.address {
-                if !symbol_table.contains_key(label) {
-                    symbol_table.entry(label).or_insert(address);
-                }
+/// How a label is referenced by a test and sequence statement
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ReferenceKind {
+    Goto,
+    Call,
+    If,
+    Iop,
+    Ira,
+    Iab,
+}
+
+impl ReferenceKind {
+    /// Address bits that a dispatch target must have cleared
+    ///
+    /// IOP, IRA and IAB branch to their target ORed with fields of IR, so the bits those
+    /// fields land on have to be zero in the target address.
+    pub fn dispatch_mask(self) -> Option<MachineAddress> {
+        match self {
+            ReferenceKind::Iop => Some(0xF),
+            ReferenceKind::Ira => Some(0xC),
+            ReferenceKind::Iab => Some(0x3C),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ReferenceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ReferenceKind::Goto => "GOTO",
+                ReferenceKind::Call => "CALL",
+                ReferenceKind::If => "IF",
+                ReferenceKind::Iop => "IOP",
+                ReferenceKind::Ira => "IRA",
+                ReferenceKind::Iab => "IAB",
+            }
+        )
+    }
+}
+
+/// Label referenced by a test and sequence statement
+///
+/// `GOTO FETCH` is assembled as EI and references nothing.
+pub fn reference<'a>(statement: &TestAndSequenceStatement<'a>) -> Option<(&'a str, ReferenceKind)> {
+    match statement {
+        TestAndSequenceStatement::Goto(label) if *label != "FETCH" => {
+            Some((label, ReferenceKind::Goto))
+        }
+        TestAndSequenceStatement::Call(label) => Some((label, ReferenceKind::Call)),
+        TestAndSequenceStatement::If(_, _, label, _) => Some((label, ReferenceKind::If)),
+        TestAndSequenceStatement::Iop(label) => Some((label, ReferenceKind::Iop)),
+        TestAndSequenceStatement::Ira(label) => Some((label, ReferenceKind::Ira)),
+        TestAndSequenceStatement::Iab(label) => Some((label, ReferenceKind::Iab)),
+        _ => None,
+    }
+}
+
+/// Instructions referencing each label, keyed by label and kind of reference
+///
+/// Built once, so that looking up how a label is referenced does not rescan the program.
+#[derive(Default)]
+pub struct ReferenceIndex<'a> {
+    references: HashMap<(&'a str, ReferenceKind), Vec<usize>>,
+}
+
+impl<'a> ReferenceIndex<'a> {
+    pub fn new(instructions: &[Instruction<'a>]) -> Self {
+        let mut references = HashMap::<(&str, ReferenceKind), Vec<usize>>::new();
+
+        for (index, instruction) in instructions.iter().enumerate() {
+            if let Some(key) = instruction
+                .test_and_sequence_statement
+                .as_ref()
+                .and_then(|(statement, _)| reference(statement))
+            {
+                references.entry(key).or_default().push(index);
             }
         }
+
+        ReferenceIndex { references }
     }
 
-    symbol_table
+    /// Kinds of reference made to `label`
+    pub fn kinds<'b>(&'b self, label: &'b str) -> impl Iterator<Item = ReferenceKind> + 'b {
+        REFERENCE_KINDS
+            .into_iter()
+            .filter(move |kind| self.references.contains_key(&(label, *kind)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&(&'a str, ReferenceKind), &Vec<usize>)> {
+        self.references.iter()
+    }
 }
 
-pub fn check_unresolved_symbols<'a>(
-    symbol_table: &SymbolTable,
+const REFERENCE_KINDS: [ReferenceKind; 6] = [
+    ReferenceKind::Goto,
+    ReferenceKind::Call,
+    ReferenceKind::If,
+    ReferenceKind::Iop,
+    ReferenceKind::Ira,
+    ReferenceKind::Iab,
+];
+
+/// Lists the labels of `symbol_table` by address, with how each one is referenced
+pub fn symbols(symbol_table: &SymbolTable, references: &ReferenceIndex) -> Vec<Symbol> {
+    let mut symbols = symbol_table
+        .iter()
+        .map(|(name, address)| Symbol {
+            name: name.to_string(),
+            address: *address,
+            references: references.kinds(name).collect(),
+        })
+        .collect::<Vec<_>>();
+    symbols.sort_by(|a, b| a.address.cmp(&b.address).then_with(|| a.name.cmp(&b.name)));
+    symbols
+}
+
+/// Labels of `symbols` by address, in the order of `symbols`
+///
+/// Built once per output, so that labelling each word does not search the symbols again.
+pub fn labels_by_address(symbols: &[Symbol]) -> HashMap<MachineAddress, Vec<&str>> {
+    let mut labels = HashMap::<MachineAddress, Vec<&str>>::new();
+    for symbol in symbols {
+        labels
+            .entry(symbol.address)
+            .or_default()
+            .push(symbol.name.as_str());
+    }
+    labels
+}
+
+/// Words the assembler gives a meaning to
+pub(crate) const KEYWORDS: [&str; 41] = [
+    "GOTO", "CALL", "RETURN", "IF", "THEN", "ELSE", "ZER", "NEG", "CRY", "OV", "CZ", "T", "IOP",
+    "IRA", "IAB", "IRB", "NSQ", "READ", "WRITE", "SET", "BY", "AND", "OR", "XOR", "SLL", "SRL",
+    "SLA", "SRA", "SNX", "SWP", "NSB", "LBUS", "RBUS", "C", "FLAG", "SAVE", "WITH", "ONE",
+    "EXECUTE", "HLT", "NEX",
+];
+
+/// Names of registers and buses
+pub(crate) const REGISTERS: [&str; 18] = [
+    "R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "RA", "RAP", "RB", "RBP", "PC", "IO", "MM",
+    "IR", "FSR", "ZERO",
+];
+
+/// Creates the symbol table, reporting every label defined more than once
+///
+/// The first definition of a label is the one entered in the table.
+pub fn create_symbol_table<'a>(
     instructions: &'a Vec<Instruction>,
-) -> HashSet<&'a str> {
-    let mut unresolved_symbols = HashSet::<&str>::new();
+) -> (SymbolTable<'a>, Vec<Error>) {
+    let mut symbol_table = SymbolTable::new();
+    let mut definitions = HashMap::<&str, &Span>::new();
+    let mut errors = Vec::<Error>::new();
 
     for instruction in instructions {
-        if let Some(test_and_sequence_statement) = &instruction.test_and_sequence_statement {
-            match test_and_sequence_statement {
-                TestAndSequenceStatement::Goto(symbol) => {
-                    if *symbol != "FETCH" && !symbol_table.contains_key(symbol) {
-                        unresolved_symbols.insert(symbol);
-                    }
-                }
-                TestAndSequenceStatement::Call(symbol) => {
-                    if !symbol_table.contains_key(symbol) {
-                        unresolved_symbols.insert(symbol);
+        if let Some((label, span)) = &instruction.label {
+            match definitions.get(label) {
+                Some(previous) => errors.push(Error::DuplicateLabel {
+                    label: label.to_string(),
+                    span: span.clone(),
+                    previous: (*previous).clone(),
+                }),
+                None => {
+                    definitions.insert(label, span);
+                    if let Some(address) = instruction.address {
+                        symbol_table.insert(label, address);
                     }
                 }
-                TestAndSequenceStatement::Iop(symbol) => {
-                    if !symbol_table.contains_key(symbol) {
-                        unresolved_symbols.insert(symbol);
-                    }
-                }
-                TestAndSequenceStatement::Ira(symbol) => {
-                    if !symbol_table.contains_key(symbol) {
-                        unresolved_symbols.insert(symbol);
-                    }
-                }
-                TestAndSequenceStatement::Iab(symbol) => {
-                    if !symbol_table.contains_key(symbol) {
-                        unresolved_symbols.insert(symbol);
-                    }
+            }
+        }
+    }
+
+    (symbol_table, errors)
+}
+
+/// Warns about labels that read like something else
+pub fn check_label_names(instructions: &[Instruction]) -> Vec<Warning> {
+    let mut warnings = Vec::<Warning>::new();
+
+    for instruction in instructions {
+        let (label, span) = match &instruction.label {
+            Some((label, span)) => (label.to_string(), span.clone()),
+            None => continue,
+        };
+        if label == "FETCH" {
+            match instruction.address {
+                Some(address) if address != 0x000 => {
+                    warnings.push(Warning::FetchLabel { span, address })
                 }
                 _ => (),
             }
+        } else if KEYWORDS.contains(&label.as_str()) {
+            warnings.push(Warning::KeywordLabel { span, label });
+        } else if REGISTERS.contains(&label.as_str()) {
+            warnings.push(Warning::RegisterLabel { span, label });
+        } else if u16::from_str_radix(&label, 16).is_ok() {
+            warnings.push(Warning::HexadecimalLabel { span, label });
+        }
+    }
+
+    warnings
+}
+
+pub fn check_unresolved_symbols(
+    symbol_table: &SymbolTable,
+    references: &ReferenceIndex,
+    instructions: &[Instruction],
+) -> Vec<Error> {
+    let mut errors = Vec::<Error>::new();
+    // labels left without an address are reported by placement::place
+    let labels = instructions
+        .iter()
+        .filter_map(|instruction| instruction.label.as_ref().map(|(label, _)| *label))
+        .collect::<HashSet<_>>();
+
+    for ((symbol, _), referrers) in references.iter() {
+        if symbol_table.contains_key(symbol) || labels.contains(symbol) {
+            continue;
+        }
+        for &index in referrers {
+            let instruction = &instructions[index];
+            if let Some((_, span)) = &instruction.test_and_sequence_statement {
+                errors.push(Error::UnresolvedSymbol {
+                    location: instruction.location(),
+                    span: span.clone(),
+                    symbol: symbol.to_string(),
+                });
+            }
         }
     }
 
-    unresolved_symbols
+    errors
 }
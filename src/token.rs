@@ -4,7 +4,7 @@ use logos::Logos;
 pub enum Token<'a> {
     #[regex(r"\.[a-zA-Z][a-zA-Z0-9]*", |lex| &lex.slice()[1..])]
     DotString(&'a str),
-    #[regex("[a-zA-Z][a-zA-Z0-9]*", |lex| lex.slice())]
+    #[regex(r"\p{L}[\p{L}0-9]*", |lex| lex.slice())]
     String(&'a str),
     #[regex("[0-9][a-fA-F0-9]*", |lex| u16::from_str_radix(lex.slice(), 16))]
     Hexadecimal(u16),
@@ -18,6 +18,22 @@ pub enum Token<'a> {
     Plus,
     #[token("-")]
     Minus,
+    #[token("/")]
+    Slash,
+    #[token("<<")]
+    LessLess,
+    #[token(">>")]
+    GreaterGreater,
+    #[token("&")]
+    Ampersand,
+    #[token("|")]
+    Bar,
+    #[token("~")]
+    Tilde,
+    #[token("(")]
+    LeftParen,
+    #[token(")")]
+    RightParen,
     #[token("$")]
     Dollar,
     #[token("@")]
@@ -28,9 +44,10 @@ pub enum Token<'a> {
     Colon,
     #[token("=")]
     Equal,
-    #[regex(r"(;[^\r]*\r)?\n")]
+    #[regex(r"\r\n|\r|\n")]
     Eol,
-    #[regex(r"[ \r\t\f]+", |_| logos::Skip)]
+    #[regex(r";[^\r\n]*", |_| logos::Skip)]
+    #[regex(r"[ \t\f]+", |_| logos::Skip)]
     #[error]
     Error,
 }
@@ -0,0 +1,682 @@
+use crate::decode::Fields;
+use crate::parser::{MachineAddress, MachineCode};
+use crate::placement::MAX_ADDRESS;
+use crate::symbol::{self, Symbol};
+use crate::Object;
+
+use logos::Span;
+use serde::Serialize;
+
+use std::io::{self, Write};
+
+/// Bytes that a 40-bit word takes in a control store image
+const WORD_BYTES: usize = 5;
+
+/// Data bytes in each Intel HEX record
+const RECORD_BYTES: usize = 16;
+
+/// Order of the bytes of a word in a control store image
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Endian {
+    /// Most significant byte first
+    #[default]
+    Big,
+    /// Least significant byte first
+    Little,
+}
+
+/// Word with every field set to do nothing: NLB NRB NAL NSH NSB NMM NSQ NTS NEX
+pub const NOP: MachineCode = 0xFFFFFFFE00;
+
+/// Layout of a control store image
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ImageOptions {
+    pub endian: Endian,
+    /// Word written to the addresses that the object leaves empty
+    pub fill: MachineCode,
+    /// Number of words in a MIF or COE file
+    pub depth: usize,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            endian: Endian::default(),
+            fill: 0,
+            depth: usize::from(MAX_ADDRESS) + 1,
+        }
+    }
+}
+
+/// Writes `object` in the `CM` format
+///
+/// The first line is `CM` followed by the title, and each of the other lines has an address
+/// and a word in hexadecimal.
+pub fn write_cm(out: &mut dyn Write, object: &Object) -> io::Result<()> {
+    write!(out, "CM {}", object.title)?;
+    for (address, code) in &object.code {
+        write!(out, "\n{address:03X}  {code:010X}")?;
+    }
+    Ok(())
+}
+
+/// Lays out the control store from address 000 up to the highest word of `object`
+fn image(object: &Object, options: &ImageOptions) -> Vec<u8> {
+    let words = object
+        .code
+        .iter()
+        .map(|(address, _)| usize::from(*address) + 1)
+        .max()
+        .unwrap_or(0);
+    let mut control_store = vec![options.fill; words];
+    for &(address, code) in &object.code {
+        control_store[usize::from(address)] = code;
+    }
+
+    control_store
+        .iter()
+        .flat_map(|code| {
+            let bytes = code.to_be_bytes();
+            let mut word = [0; WORD_BYTES];
+            word.copy_from_slice(&bytes[bytes.len() - WORD_BYTES..]);
+            if options.endian == Endian::Little {
+                word.reverse();
+            }
+            word
+        })
+        .collect()
+}
+
+/// Writes the control store as raw bytes, five for each word
+pub fn write_binary(
+    out: &mut dyn Write,
+    object: &Object,
+    options: &ImageOptions,
+) -> io::Result<()> {
+    out.write_all(&image(object, options))
+}
+
+/// Writes the control store as Intel HEX, five bytes for each word
+///
+/// The byte address of the word at address `a` is `5 * a`.
+pub fn write_intel_hex(
+    out: &mut dyn Write,
+    object: &Object,
+    options: &ImageOptions,
+) -> io::Result<()> {
+    let record = |out: &mut dyn Write, address: usize, kind: u8, data: &[u8]| {
+        let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+        bytes.extend_from_slice(data);
+        let checksum = bytes
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+            .wrapping_neg();
+        write!(out, ":")?;
+        for byte in bytes.iter().chain([checksum].iter()) {
+            write!(out, "{byte:02X}")?;
+        }
+        writeln!(out)
+    };
+
+    for (index, data) in image(object, options).chunks(RECORD_BYTES).enumerate() {
+        record(out, index * RECORD_BYTES, 0x00, data)?;
+    }
+    record(out, 0, 0x01, &[])
+}
+
+/// Name of the ROM module generated for `object`, made from its title
+fn module_name(object: &Object) -> String {
+    let name = object
+        .title
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    match name.trim_matches('_') {
+        "" => "micro1_rom".to_string(),
+        name if name.starts_with(|c: char| c.is_ascii_digit()) => format!("m{name}_rom"),
+        name => format!("{name}_rom"),
+    }
+}
+
+/// Words of `object` in address order, each with the labels placed on it
+fn labeled_words(object: &Object) -> Vec<(MachineAddress, MachineCode, String)> {
+    let labels = symbol::labels_by_address(&object.symbols);
+    let mut words = object
+        .code
+        .iter()
+        .map(|&(address, code)| {
+            let labels = labels
+                .get(&address)
+                .map_or(String::new(), |labels| labels.join(" "));
+            (address, code, labels)
+        })
+        .collect::<Vec<_>>();
+    words.sort_by_key(|(address, _, _)| *address);
+    words
+}
+
+/// Writes a Verilog module that looks the control store up in a `case` statement
+///
+/// Addresses that the object leaves empty read as the fill word.
+pub fn write_verilog(
+    out: &mut dyn Write,
+    object: &Object,
+    options: &ImageOptions,
+) -> io::Result<()> {
+    writeln!(out, "// {}", object.title)?;
+    writeln!(out, "module {} (", module_name(object))?;
+    writeln!(out, "    input  wire [11:0] address,")?;
+    writeln!(out, "    output reg  [39:0] data")?;
+    writeln!(out, ");")?;
+    writeln!(out, "    always @(*) begin")?;
+    writeln!(out, "        case (address)")?;
+    for (address, code, labels) in labeled_words(object) {
+        let line = format!("            12'h{address:03X}: data = 40'h{code:010X};");
+        if labels.is_empty() {
+            writeln!(out, "{line}")?;
+        } else {
+            writeln!(out, "{line} // {labels}")?;
+        }
+    }
+    writeln!(
+        out,
+        "            default: data = 40'h{:010X};",
+        options.fill
+    )?;
+    writeln!(out, "        endcase")?;
+    writeln!(out, "    end")?;
+    writeln!(out, "endmodule")
+}
+
+/// Writes a VHDL entity that looks the control store up in a `case` statement
+///
+/// Addresses that the object leaves empty read as the fill word.
+pub fn write_vhdl(out: &mut dyn Write, object: &Object, options: &ImageOptions) -> io::Result<()> {
+    let name = module_name(object);
+    writeln!(out, "-- {}", object.title)?;
+    writeln!(out, "library ieee;")?;
+    writeln!(out, "use ieee.std_logic_1164.all;")?;
+    writeln!(out)?;
+    writeln!(out, "entity {name} is")?;
+    writeln!(out, "    port (")?;
+    writeln!(out, "        address : in  std_logic_vector(11 downto 0);")?;
+    writeln!(out, "        data    : out std_logic_vector(39 downto 0)")?;
+    writeln!(out, "    );")?;
+    writeln!(out, "end entity;")?;
+    writeln!(out)?;
+    writeln!(out, "architecture rtl of {name} is")?;
+    writeln!(out, "begin")?;
+    writeln!(out, "    process (address)")?;
+    writeln!(out, "    begin")?;
+    writeln!(out, "        case address is")?;
+    for (address, code, labels) in labeled_words(object) {
+        let line = format!("            when x\"{address:03X}\" => data <= x\"{code:010X}\";");
+        if labels.is_empty() {
+            writeln!(out, "{line}")?;
+        } else {
+            writeln!(out, "{line} -- {labels}")?;
+        }
+    }
+    writeln!(
+        out,
+        "            when others => data <= x\"{:010X}\";",
+        options.fill
+    )?;
+    writeln!(out, "        end case;")?;
+    writeln!(out, "    end process;")?;
+    writeln!(out, "end architecture;")
+}
+
+/// Writes the control store for `$readmemh`, one word per line from address 000
+///
+/// Empty addresses up to the highest word hold the fill word.
+pub fn write_memh(out: &mut dyn Write, object: &Object, options: &ImageOptions) -> io::Result<()> {
+    let words = labeled_words(object);
+    let mut next = 0;
+    writeln!(out, "// {}", object.title)?;
+    for (address, code, labels) in words {
+        for _ in next..address {
+            writeln!(out, "{:010X}", options.fill)?;
+        }
+        if labels.is_empty() {
+            writeln!(out, "{code:010X}")?;
+        } else {
+            writeln!(out, "{code:010X} // {labels}")?;
+        }
+        next = address + 1;
+    }
+    Ok(())
+}
+
+/// Fails if a word of `object` lies beyond the depth of a memory initialization file
+fn check_depth(object: &Object, options: &ImageOptions) -> io::Result<()> {
+    match object
+        .code
+        .iter()
+        .find(|(address, _)| usize::from(*address) >= options.depth)
+    {
+        Some((address, _)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the word at {address:03X} lies beyond the depth of {}",
+                options.depth
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Writes an Altera memory initialization file of `depth` 40-bit words
+///
+/// Runs of empty addresses are written as one range holding the fill word.
+pub fn write_mif(out: &mut dyn Write, object: &Object, options: &ImageOptions) -> io::Result<()> {
+    check_depth(object, options)?;
+    let fill = |out: &mut dyn Write, from: usize, to: usize| match to - from {
+        0 => Ok(()),
+        1 => writeln!(out, "\t{from:03X} : {:010X};", options.fill),
+        _ => writeln!(
+            out,
+            "\t[{from:03X}..{:03X}] : {:010X};",
+            to - 1,
+            options.fill
+        ),
+    };
+
+    writeln!(out, "-- {}", object.title)?;
+    writeln!(out, "WIDTH=40;")?;
+    writeln!(out, "DEPTH={};", options.depth)?;
+    writeln!(out)?;
+    writeln!(out, "ADDRESS_RADIX=HEX;")?;
+    writeln!(out, "DATA_RADIX=HEX;")?;
+    writeln!(out)?;
+    writeln!(out, "CONTENT BEGIN")?;
+    let mut next = 0;
+    for (address, code, labels) in labeled_words(object) {
+        fill(out, next, usize::from(address))?;
+        if labels.is_empty() {
+            writeln!(out, "\t{address:03X} : {code:010X};")?;
+        } else {
+            writeln!(out, "\t{address:03X} : {code:010X}; -- {labels}")?;
+        }
+        next = usize::from(address) + 1;
+    }
+    fill(out, next, options.depth)?;
+    writeln!(out, "END;")
+}
+
+/// Writes a Xilinx coefficient file of `depth` 40-bit words
+pub fn write_coe(out: &mut dyn Write, object: &Object, options: &ImageOptions) -> io::Result<()> {
+    check_depth(object, options)?;
+    let mut control_store = vec![options.fill; options.depth];
+    for &(address, code) in &object.code {
+        control_store[usize::from(address)] = code;
+    }
+
+    writeln!(out, "; {}", object.title)?;
+    writeln!(out, "memory_initialization_radix=16;")?;
+    writeln!(out, "memory_initialization_vector=")?;
+    for (index, code) in control_store.iter().enumerate() {
+        let end = if index + 1 == control_store.len() {
+            ';'
+        } else {
+            ','
+        };
+        writeln!(out, "{code:010X}{end}")?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Json<'a> {
+    title: &'a str,
+    symbols: &'a [Symbol],
+    code: Vec<JsonWord<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonWord<'a> {
+    address: MachineAddress,
+    label: Option<&'a str>,
+    span: Option<Span>,
+    word: MachineCode,
+    fields: Fields,
+}
+
+/// Writes `object` as JSON
+///
+/// The object has the `title`, the `symbols` sorted by address as in the map file, and the
+/// words in source order in `code`. Each word has its `address`, the `label` placed on it, the
+/// `span` of the instruction head as `start` and `end` byte offsets, the `word` as a number,
+/// and its `fields` by mnemonic, such as `"lb": "PC"`, with the `literal` or branch `target`.
+/// `label` and `span` are `null` when the object does not carry them, and so are `literal` and
+/// `target` when the word has none. `ts` and `ex` are left out of the fields of a word that
+/// uses their bits for a literal or an address.
+///
+/// Spans are offsets into the text given to [`assemble`](crate::assemble), which for a program
+/// with included files is the spliced text of its [`SourceMap`](crate::SourceMap);
+/// [`SourceMap::locate`](crate::SourceMap::locate) maps them back to the file they were
+/// written in.
+pub fn write_json(out: &mut dyn Write, object: &Object) -> io::Result<()> {
+    let labels = symbol::labels_by_address(&object.symbols);
+    let json = Json {
+        title: &object.title,
+        symbols: &object.symbols,
+        code: object
+            .code
+            .iter()
+            .enumerate()
+            .map(|(index, &(address, word))| JsonWord {
+                address,
+                label: labels.get(&address).map(|labels| labels[0]),
+                span: object.spans.get(index).cloned(),
+                word,
+                fields: Fields::decode(address, word),
+            })
+            .collect(),
+    };
+    serde_json::to_writer_pretty(&mut *out, &json)?;
+    writeln!(out)
+}
+
+/// Output format for assembled objects
+///
+/// Implement this to add a format to a [`WriterRegistry`]. The object carries the title, the
+/// resolved symbols and the words in source order.
+pub trait ObjectWriter {
+    /// Name that selects the format, such as `cm`
+    fn name(&self) -> &str;
+
+    /// Extension given to the output file when no path is given
+    fn extension(&self) -> &str;
+
+    fn write(&self, out: &mut dyn Write, object: &Object, options: &ImageOptions)
+        -> io::Result<()>;
+}
+
+type WriteFn = fn(&mut dyn Write, &Object, &ImageOptions) -> io::Result<()>;
+
+/// Format implemented by one of the `write_*` functions of this crate
+struct Builtin {
+    name: &'static str,
+    extension: &'static str,
+    write: WriteFn,
+}
+
+impl ObjectWriter for Builtin {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn extension(&self) -> &str {
+        self.extension
+    }
+
+    fn write(
+        &self,
+        out: &mut dyn Write,
+        object: &Object,
+        options: &ImageOptions,
+    ) -> io::Result<()> {
+        (self.write)(out, object, options)
+    }
+}
+
+const BUILTINS: [(&str, &str, WriteFn); 9] = [
+    ("cm", "o", |out, object, _| write_cm(out, object)),
+    ("ihex", "hex", write_intel_hex),
+    ("bin", "bin", write_binary),
+    ("verilog", "v", write_verilog),
+    ("vhdl", "vhd", write_vhdl),
+    ("memh", "mem", write_memh),
+    ("mif", "mif", write_mif),
+    ("coe", "coe", write_coe),
+    ("json", "json", |out, object, _| write_json(out, object)),
+];
+
+/// Output formats by name
+///
+/// The default registry has every format of this crate: `cm`, `ihex`, `bin`, `verilog`, `vhdl`,
+/// `memh`, `mif`, `coe` and `json`.
+pub struct WriterRegistry {
+    writers: Vec<Box<dyn ObjectWriter>>,
+}
+
+impl WriterRegistry {
+    /// Creates a registry with no format
+    pub fn new() -> Self {
+        WriterRegistry {
+            writers: Vec::new(),
+        }
+    }
+
+    /// Adds `writer`, replacing the format of the same name
+    pub fn register(&mut self, writer: Box<dyn ObjectWriter>) {
+        match self
+            .writers
+            .iter_mut()
+            .find(|registered| registered.name() == writer.name())
+        {
+            Some(registered) => *registered = writer,
+            None => self.writers.push(writer),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn ObjectWriter> {
+        self.writers
+            .iter()
+            .find(|writer| writer.name() == name)
+            .map(AsRef::as_ref)
+    }
+
+    /// Names of the formats in the order they were registered
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.writers.iter().map(|writer| writer.name())
+    }
+}
+
+impl Default for WriterRegistry {
+    fn default() -> Self {
+        let mut registry = WriterRegistry::new();
+        for (name, extension, write) in BUILTINS {
+            registry.register(Box::new(Builtin {
+                name,
+                extension,
+                write,
+            }));
+        }
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        write_binary, write_coe, write_intel_hex, write_json, write_memh, write_mif, write_verilog,
+        Endian, ImageOptions, ObjectWriter, WriterRegistry, NOP,
+    };
+    use crate::{assemble, read_object, Object, Options};
+
+    use std::io::{self, Write};
+
+    fn object() -> Object {
+        read_object("CM IMAGE\n000  AA1F0FFE01\n002  FFFFF5FE01\n004  0123456789\n").unwrap()
+    }
+
+    #[test]
+    fn test_binary() {
+        let mut out = Vec::new();
+        let options = ImageOptions {
+            endian: Endian::Little,
+            fill: NOP,
+            ..ImageOptions::default()
+        };
+        write_binary(&mut out, &object(), &options).unwrap();
+        assert_eq!(out.len(), 25);
+        assert_eq!(out[..5], [0x01, 0xFE, 0x0F, 0x1F, 0xAA]);
+        assert_eq!(out[5..10], [0x00, 0xFE, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(out[20..], [0x89, 0x67, 0x45, 0x23, 0x01]);
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let mut out = Vec::new();
+        write_intel_hex(&mut out, &object(), &ImageOptions::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+:10000000AA1F0FFE010000000000FFFFF5FE010027
+:090010000000000001234567898E
+:00000001FF
+"
+        );
+    }
+
+    const SOURCE: &str = ".TITLE ROM\n* FETCH: 000\n\tIR := MM\n* LOOP: 002\n\tGOTO LOOP\n.END\n";
+
+    #[test]
+    fn test_verilog() {
+        let object = assemble(SOURCE, &Options::default()).unwrap();
+        let mut out = Vec::new();
+        write_verilog(&mut out, &object, &ImageOptions::default()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("// ROM\nmodule rom_rom (\n"));
+        assert!(out.contains("            12'h000: data = 40'hCFFFFFE800; // FETCH\n"));
+        assert!(out.contains("            12'h002: data = 40'hFFFFF01E02; // LOOP\n"));
+        assert!(out.contains("            default: data = 40'h0000000000;\n"));
+    }
+
+    #[test]
+    fn test_memh() {
+        let object = assemble(SOURCE, &Options::default()).unwrap();
+        let mut out = Vec::new();
+        let options = ImageOptions {
+            fill: NOP,
+            ..ImageOptions::default()
+        };
+        write_memh(&mut out, &object, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "// ROM\nCFFFFFE800 // FETCH\nFFFFFFFE00\nFFFFF01E02 // LOOP\n"
+        );
+    }
+
+    #[test]
+    fn test_mif() {
+        let object = assemble(SOURCE, &Options::default()).unwrap();
+        let mut out = Vec::new();
+        let options = ImageOptions {
+            fill: NOP,
+            depth: 8,
+            ..ImageOptions::default()
+        };
+        write_mif(&mut out, &object, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+-- ROM
+WIDTH=40;
+DEPTH=8;
+
+ADDRESS_RADIX=HEX;
+DATA_RADIX=HEX;
+
+CONTENT BEGIN
+\t000 : CFFFFFE800; -- FETCH
+\t001 : FFFFFFFE00;
+\t002 : FFFFF01E02; -- LOOP
+\t[003..007] : FFFFFFFE00;
+END;
+"
+        );
+
+        let options = ImageOptions {
+            depth: 2,
+            ..ImageOptions::default()
+        };
+        assert!(write_mif(&mut Vec::new(), &object, &options).is_err());
+    }
+
+    #[test]
+    fn test_coe() {
+        let object = assemble(SOURCE, &Options::default()).unwrap();
+        let mut out = Vec::new();
+        let options = ImageOptions {
+            depth: 4,
+            ..ImageOptions::default()
+        };
+        write_coe(&mut out, &object, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+; ROM
+memory_initialization_radix=16;
+memory_initialization_vector=
+CFFFFFE800,
+0000000000,
+FFFFF01E02,
+0000000000;
+"
+        );
+    }
+
+    struct Words;
+
+    impl ObjectWriter for Words {
+        fn name(&self) -> &str {
+            "cm"
+        }
+
+        fn extension(&self) -> &str {
+            "txt"
+        }
+
+        fn write(&self, out: &mut dyn Write, object: &Object, _: &ImageOptions) -> io::Result<()> {
+            writeln!(out, "{} words", object.code.len())
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = WriterRegistry::default();
+        assert_eq!(
+            registry.names().collect::<Vec<_>>(),
+            ["cm", "ihex", "bin", "verilog", "vhdl", "memh", "mif", "coe", "json"]
+        );
+
+        registry.register(Box::new(Words));
+        let writer = registry.get("cm").unwrap();
+        assert_eq!(writer.extension(), "txt");
+        let mut out = Vec::new();
+        writer
+            .write(&mut out, &object(), &ImageOptions::default())
+            .unwrap();
+        assert_eq!(out, b"3 words\n");
+        assert!(registry.get("srec").is_none());
+    }
+
+    #[test]
+    fn test_json() {
+        let object = assemble(SOURCE, &Options::default()).unwrap();
+        let mut out = Vec::new();
+        write_json(&mut out, &object).unwrap();
+        let json = serde_json::from_slice::<serde_json::Value>(&out).unwrap();
+
+        assert_eq!(json["title"], "ROM");
+        assert_eq!(json["symbols"][1]["name"], "LOOP");
+        let word = &json["code"][1];
+        assert_eq!(word["address"], 2);
+        assert_eq!(word["label"], "LOOP");
+        assert_eq!(word["span"]["start"], 34);
+        assert_eq!(word["word"], 0xFFFFF01E02u64);
+        assert_eq!(word["fields"]["sq"], "B");
+        assert!(word["fields"].get("ts").is_none());
+        assert_eq!(word["fields"]["ex"], "NEX");
+        assert_eq!(word["fields"]["target"], 2);
+    }
+}
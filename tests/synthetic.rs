@@ -0,0 +1,36 @@
+//! Checks the program that the benchmarks assemble, so that they keep measuring valid input
+
+#[path = "../benches/synthetic/mod.rs"]
+mod synthetic;
+
+use std::collections::HashSet;
+
+#[test]
+fn test_synthetic_placement() {
+    let object =
+        rm1masm::assemble(&synthetic::synthesize(64), &rm1masm::Options::default()).unwrap();
+    // FETCH, four runs of IR := MM, IOP, the table label and its 16 entries, then the routines
+    assert_eq!(object.code.len(), 1 + 4 * 19 + 64 * 11);
+
+    let address = |name: &str| {
+        object
+            .symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .unwrap()
+            .address
+    };
+    // each jump table is placed at the next 16-word boundary after its IOP
+    assert_eq!(
+        ["T0", "T1", "T2", "T3"].map(address),
+        [0x010, 0x030, 0x050, 0x070]
+    );
+    assert_eq!(["R0", "R1", "R63E"].map(address), [0x081, 0x08C, 0x340]);
+
+    let addresses = object
+        .code
+        .iter()
+        .map(|(address, _)| *address)
+        .collect::<HashSet<_>>();
+    assert_eq!(addresses.len(), object.code.len());
+}